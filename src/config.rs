@@ -0,0 +1,39 @@
+use std::{collections::HashMap, fs, str::FromStr};
+
+pub const DEFAULT_CONFIG_PATH: &str = "sim868.conf";
+
+/// Emulator settings read from a `key = value` file, `#` starts a comment line.
+#[derive(Clone, Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Config {
+        let mut config = Config::default();
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some((key, value)) = line.split_once('=') {
+                    config
+                        .values
+                        .insert(key.trim().to_owned(), value.trim().to_owned());
+                }
+            }
+        }
+        config
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    pub fn get_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.get(key)
+            .and_then(|v| v.parse::<T>().ok())
+            .unwrap_or(default)
+    }
+}
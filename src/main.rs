@@ -1,10 +1,14 @@
 use std::{
     error::Error,
     io,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
     time::Duration,
 };
 
+mod config;
 mod sim868;
 mod ui;
 mod utils;
@@ -17,6 +21,10 @@ use crossterm::{
 use ratatui::{prelude::*, Terminal};
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let config_path = std::env::args()
+        .nth(1)
+        .unwrap_or(config::DEFAULT_CONFIG_PATH.to_owned());
+    let config = config::Config::load(&config_path);
     let ports = serialport::available_ports().expect("No ports found!");
     println!("Please select your device connected port");
     for (index, p) in ports.iter().enumerate() {
@@ -32,8 +40,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         .expect("Not a valid number")]
     .port_name
     .clone();
-    let (port_tx, port_rx) = channel::<String>();
-    let rx = utils::serial::read_line_thread(port, port_rx);
+    let (port_tx, port_rx) = channel::<Vec<u8>>();
+    let (ctrl_tx, ctrl_rx) = channel::<utils::serial::PortControl>();
+    let lines = Arc::new(utils::serial::ControlLines::new());
+    let wiring = utils::serial::Wiring::from_config(&config);
+    let baud = sim868::start_baud(&config);
+    if config
+        .get("serial.baud")
+        .is_some_and(|b| b.parse() != Ok(baud))
+    {
+        println!(
+            "serial.baud is not a speed AT+IPR takes, starting at {}",
+            baud
+        );
+    }
+    let buffer = utils::serial::InputBuffer::from_config(&config);
+    let rx = utils::serial::read_line_thread(
+        port,
+        baud as u32,
+        port_rx,
+        ctrl_rx,
+        lines.clone(),
+        wiring,
+        buffer,
+    );
     // println!("You have selected {:?}", rx);
     // let mut sim_device = Sim868::new(true, GnssConfiguration::default());
     // sim_device.start_gnss();
@@ -52,7 +82,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let res = ui::run_ui(&mut terminal, rx, port_tx);
+    let res = ui::run_ui(&mut terminal, rx, port_tx, ctrl_tx, lines, config);
 
     // restore terminal
     disable_raw_mode()?;
@@ -1,12 +1,21 @@
 use std::{
     str,
     sync::{
-        mpsc::{channel, Receiver, Sender},
+        atomic::{AtomicU64, AtomicU8},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
         Arc, Mutex,
     },
     time::Duration,
 };
 
+use crate::{
+    config::Config,
+    utils::{
+        serial::{ControlLines, PortControl},
+        timer::Ticker,
+    },
+};
+
 #[macro_export]
 macro_rules! at {
     ($cmd: expr) => {
@@ -14,77 +23,172 @@ macro_rules! at {
     };
 }
 
+mod bearer;
+mod cell;
+mod dns;
+mod ftp;
+mod gnss;
+mod http;
+mod identity;
+mod modem;
+mod ntp;
+mod power;
+mod profile;
+mod rtc;
+mod sleep;
+mod tcpip;
+
+pub use gnss::{GnssConfiguration, Injection};
+
+/// Changes the GNSS worker thread picks up as soon as they are sent
 #[derive(PartialEq)]
 pub enum GnssConfig {
-    URC(u8),
-    STATUS(bool),
+    /// AT+CGNSURC changed, the next report is due on the next fix
+    Urc(u8),
+    /// whether the worker sends anything to the port
+    Status(bool),
+    /// the engine was powered up or down
+    Power(bool),
+    /// time between fixes in milliseconds, PMTK220
+    Rate(u64),
 }
 
-const AT_CFUN: &str = "AT+CFUN";
 const AT_IPR: &str = "AT+IPR";
-const AT_AND_W: &str = "AT&W";
-const CME_ERROR: &str = "+CME ERROR:0";
+/// Fixed speeds AT+IPR takes
+const IPR_RATES: [usize; 10] = [
+    1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800,
+];
 const OK: &str = "OK";
 const AT_ECHO: &str = "ATE";
 const AT_CMEE: &str = "AT+CMEE";
-const AT_CGMI: &str = "AT+CGMI";
-const AT_CGMM: &str = "AT+CGMM";
-const AT_CGSN: &str = "AT+CGSN";
-const AT_CGMR: &str = "AT+CGMR";
 const AT_CREG: &str = "AT+CREG";
+const ERROR: &str = "ERROR";
+
+/// Splits the arguments of a `<cmd>=a,"b",c` line, quotes are removed and commas inside them kept.
+fn params(line: &str, cmd: &str) -> Vec<String> {
+    let args = line[cmd.len().min(line.len())..].trim();
+    let Some(args) = args.strip_prefix('=') else {
+        return vec![];
+    };
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => result.push(std::mem::take(&mut current).trim().to_owned()),
+            _ => current.push(c),
+        }
+    }
+    result.push(current.trim().to_owned());
+    result
+}
+
+/// Answers the way they go out on the port
+fn bytes(answer: Vec<String>) -> Vec<Vec<u8>> {
+    answer.into_iter().map(String::into_bytes).collect()
+}
+
+/// `serial.baud` when AT+IPR takes it or it is 0 for auto-bauding, 115200 otherwise
+pub fn start_baud(config: &Config) -> usize {
+    let baud = config.get_or("serial.baud", 115_200);
+    if ipr_valid(baud) {
+        baud
+    } else {
+        115_200
+    }
+}
+
+/// A speed AT+IPR takes, 0 being auto-bauding
+fn ipr_valid(rate: usize) -> bool {
+    rate == 0 || IPR_RATES.contains(&rate)
+}
+
+/// What the next chunk coming from the host belongs to when it is not an AT command
+pub enum PendingInput {
+    CipSend(usize),
+    HttpData,
+    FtpPut,
+}
 
 #[derive(PartialEq)]
 pub struct GSMConfig {
+    /// speed of the UART, 0 while auto-bauding
     baudrate: usize,
+    clip: u8,
+    cnmi: [u8; 5],
     echo: bool,
     cmee: u8,
+    /// `AT+CREG` URC mode
+    creg: u8,
     fun_mode: Option<u8>,
     rst_mod: Option<u8>,
 }
 
-pub struct GnssConfiguration {
-    pub urc: u8,
-    pub urc_enabled: bool,
-    pub power: bool,
-    // pub tx: Option<Sender<GnssConfig>>,
-}
-impl GnssConfiguration {
-    pub fn default() -> GnssConfiguration {
-        GnssConfiguration {
-            urc: 5,
-            urc_enabled: false,
-            power: false,
-            // tx: None,
-        }
-    }
-    pub fn set_tx(&mut self, tx: Sender<GnssConfig>) {
-        // self.tx = Some(tx);
-    }
-    pub fn power(&self) -> bool {
-        self.power
-    }
-}
 pub struct Sim868 {
     pub power: bool,
+    /// counts power and radio changes, threads of an earlier state stop when it moves on
+    pub power_cycle: Arc<AtomicU64>,
     pub gnss: Arc<Mutex<GnssConfiguration>>,
     pub working: bool,
     pub configs: GSMConfig,
     pub reg_status: Arc<Mutex<u8>>,
-    // pub baudrate: usize, // pub port_tx: Option<Sender<String>>,
+    /// what `reg_status` goes back to once the radio is on again
+    pub registration: Arc<AtomicU8>,
+    pub config: Config,
+    pub tcpip: tcpip::TcpIp,
+    pub dns: dns::Dns,
+    pub bearers: Vec<bearer::BearerProfile>,
+    pub http: http::Http,
+    pub ssl_options: http::SslOptions,
+    pub ftp: ftp::Ftp,
+    pub ntp: ntp::Ntp,
+    pub engineering: cell::Engineering,
+    /// read once the configuration is in place, see `load_identity`
+    pub identity: identity::Identity,
+    pub pending_input: Option<PendingInput>,
+    pub sleep: sleep::Sleep,
+    /// modem control lines of the UART
+    pub lines: Arc<ControlLines>,
+    pub modem: modem::Modem,
+    rtc: Arc<Mutex<rtc::Rtc>>,
+    port_ctrl: Option<Sender<PortControl>>,
+    // pub baudrate: usize, // pub port_tx: Option<Sender<Vec<u8>>>,
 }
 impl Sim868 {
     pub fn new(active: bool, gnss_conf: GnssConfiguration) -> Sim868 {
         Sim868 {
             power: active,
-            gnss: Arc::new(Mutex::new(GnssConfiguration::default())),
+            power_cycle: Arc::new(AtomicU64::new(0)),
+            gnss: Arc::new(Mutex::new(gnss_conf)),
             reg_status: Arc::new(Mutex::new(0)),
+            registration: Arc::new(AtomicU8::new(0)),
             working: true,
+            config: Config::default(),
+            tcpip: tcpip::TcpIp::new(),
+            dns: dns::Dns::new(),
+            bearers: vec![bearer::BearerProfile::new(); bearer::BEARER_PROFILES],
+            http: http::Http::new(),
+            ssl_options: http::SslOptions::new(),
+            ftp: ftp::Ftp::new(),
+            ntp: ntp::Ntp::new(),
+            engineering: cell::Engineering::new(),
+            identity: identity::Identity::from_config(&Config::default()).0,
+            pending_input: None,
+            sleep: sleep::Sleep::from_config(&Config::default()),
+            lines: Arc::new(ControlLines::new()),
+            modem: modem::Modem::new(),
+            rtc: Arc::new(Mutex::new(rtc::Rtc::new())),
+            port_ctrl: None,
             configs: GSMConfig {
                 baudrate: 115200,
+                clip: 0,
+                cnmi: [2, 1, 0, 0, 0],
                 echo: false,
                 fun_mode: None,
                 rst_mod: None,
                 cmee: 0,
+                creg: 0,
             },
         }
     }
@@ -96,109 +200,158 @@ impl Sim868 {
     // }
     pub fn start(&self, rx: Receiver<GSMConfig>) {}
 
-    pub fn start_gnss(&mut self, port_tx: Sender<String>) -> Sender<GnssConfig> {
+    pub fn set_port_control(&mut self, ctrl_tx: Sender<PortControl>) {
+        self.port_ctrl = Some(ctrl_tx);
+    }
+
+    /// Switches the port to data mode, the next chunk read is handed back to `process_at` as `pending`
+    fn expect_data(&mut self, pending: PendingInput, read: PortControl) {
+        if let Some(ctrl) = &self.port_ctrl {
+            ctrl.send(read).unwrap();
+        }
+        self.pending_input = Some(pending);
+    }
+
+    fn process_data(&mut self, pending: PendingInput, data: &[u8]) -> Vec<Vec<u8>> {
+        let answer = match pending {
+            PendingInput::CipSend(link) => self.cipsend_data(link, data),
+            PendingInput::HttpData => self.httpdata_received(data),
+            PendingInput::FtpPut => self.ftpput_data(data),
+        };
+        bytes(answer)
+    }
+
+    /// Spawns the GNSS worker: it sleeps until the next fix is due or a change comes in on the
+    /// returned sender, which the GNSS model also holds to report its own changes
+    pub fn start_gnss(&mut self, port_tx: Sender<Vec<u8>>) -> Sender<GnssConfig> {
         let (tx, rx) = channel::<GnssConfig>();
-        // self.gnss.tx = Some(tx.clone());
-        let shared_self = self.gnss.clone(); //Arc::new(Mutex::new(self.gnss));
-
-        if self.power {
-            std::thread::spawn(move || {
-                let mut urc = 5;
-                loop {
-                    {
-                        if shared_self.lock().unwrap().power {
-                            match rx.try_recv() {
-                                Ok(conf) => match conf {
-                                    GnssConfig::URC(n) => {
-                                        // set the sim868 emulator gnss urc
-                                        urc = n.max(1);
-                                    }
-                                    GnssConfig::STATUS(active) => {
-                                        // stop sending output from this thread
-                                    }
-                                },
-                                Err(e) => {}
-                            }
+        let shared_self = self.gnss.clone();
+        shared_self.lock().unwrap().set_tx(tx.clone());
 
-                            port_tx
-                                .send("AT+UGNSINF=1,2,4,1,2,4,12,4,1".to_owned())
-                                .unwrap();
-                            std::thread::sleep(Duration::from_secs(urc as u64));
+        std::thread::spawn(move || {
+            let interval = shared_self.lock().unwrap().fix_interval_ms;
+            let mut ticker = Ticker::new(Duration::from_millis(interval));
+            let mut active = true;
+            let mut fix = 0;
+            // fixes left until the next +UGNSINF
+            let mut urc_countdown = 1;
+            loop {
+                match rx.recv_timeout(ticker.remaining()) {
+                    Ok(GnssConfig::Urc(_)) => urc_countdown = 1,
+                    Ok(GnssConfig::Status(status)) => active = status,
+                    Ok(GnssConfig::Power(on)) => {
+                        if on {
+                            fix = 0;
+                            urc_countdown = 1;
+                            let interval = shared_self.lock().unwrap().fix_interval_ms;
+                            ticker.restart(Duration::from_millis(interval));
                         }
                     }
+                    Ok(GnssConfig::Rate(interval)) => {
+                        ticker.restart(Duration::from_millis(interval))
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        ticker.tick();
+                        let mut gnss = shared_self.lock().unwrap();
+                        gnss.run_scenario();
+                        if !gnss.power {
+                            continue;
+                        }
+                        urc_countdown -= 1;
+                        let report = urc_countdown == 0;
+                        if report {
+                            urc_countdown = gnss.urc.max(1);
+                        }
+                        let output = gnss.output(fix, report);
+                        fix += 1;
+                        if active {
+                            for line in output {
+                                port_tx.send(line.into()).unwrap();
+                            }
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
                 }
-            });
-        } else {
-            println!("Failed to run gnss because sim is not activated yet");
-        }
+            }
+        });
         tx
     }
 
-    pub fn process_at(&mut self, at_cmd: &str, tx: Sender<String>) -> Option<Vec<String>> {
-        // if self.power {
-        // if at_cmd.trim() == "AT" {
+    /// Answers what came in from the host, a command line or the data a command waits for
+    pub fn process_at(&mut self, input: &[u8], tx: Sender<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+        // a module that is off has its UART down, whatever comes in is lost
+        if !self.power {
+            return Some(vec![]);
+        }
+        if let Some(pending) = self.pending_input.take() {
+            return Some(self.process_data(pending, input));
+        }
+        let tx = self.urc_channel(tx);
+        let Some(at_cmd) = self.wake(&String::from_utf8_lossy(input)) else {
+            return Some(vec![]);
+        };
+        let at_cmd = at_cmd.as_str();
         let mut res = vec![];
         if self.configs.echo {
             res.push(at_cmd.to_owned() + "\r");
         }
 
         if at_cmd.len() <= 2 {
-            return Some(vec!["AT\r\nOK".to_owned()]);
-        } else if at_cmd.starts_with(AT_CFUN) {
-            let t = at_cmd.as_bytes()[AT_CFUN.len()] as char;
-            if t == '=' {
-                let func = at_cmd.as_bytes()[AT_CFUN.len() + 1] as u8 - '0' as u8;
-                if func == 0 || func == 1 || func == 4 {
-                    self.configs.fun_mode = Some(func);
-                    if func == 1 {
-                        res.push(at!("+CREG: 0"));
-                        res.push(at!("+CPIN: READY"));
-                        res.push(at!("+CGREG:2"));
-                        res.push(at!("Call Ready"));
-                        res.push(at!(OK));
-                    }
-                }
-                if at_cmd.len() > AT_CFUN.len() + 2 {
-                    let rst = at_cmd.as_bytes()[AT_CFUN.len() + 3] as u8 - '0' as u8;
-                    self.configs.fun_mode = Some(rst);
-                    if rst == 1 {
-                        res.push(at!("+CFUN=1"));
-                    }
-                }
-                return Some(res);
-            } else if t == '?' {
-                return Some(vec![t.to_string()]);
-            } else {
-                return Some(vec![CME_ERROR.to_owned()]);
-            }
+            return Some(bytes(vec!["AT\r\nOK".to_owned()]));
         } else if at_cmd.starts_with(AT_IPR) {
-            res.push(at!(OK));
-            // vec![self.ipr(at_cmd).to_owned()
-            return Some(res);
-        } else if at_cmd.starts_with(AT_AND_W) {
-            res.push(at!(OK));
-            return Some(res);
+            res.extend(self.ipr(at_cmd));
+            return Some(bytes(res));
+        } else if let Some(answer) = self.profile_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
         } else if at_cmd.starts_with(AT_ECHO) {
             res.push(self.echo(at_cmd));
-            return Some(res);
+            return Some(bytes(res));
         } else if at_cmd.starts_with(AT_CMEE) {
-            res.push(self.cmee(at_cmd));
-            return Some(res);
-        } else if at_cmd.starts_with(AT_CGMI)
-            || at_cmd.starts_with(AT_CGMM)
-            || at_cmd.starts_with(AT_CGSN)
-            || at_cmd.starts_with(AT_CGMR)
-        {
-            res.push(self.manu_info(at_cmd));
-            res.push(at!(OK).to_owned());
-            return Some(res);
+            res.extend(self.cmee(at_cmd));
+            return Some(bytes(res));
+        } else if let Some(answer) = self.identity_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
         } else if at_cmd.starts_with(AT_CREG) {
-            self.creg_thread(tx.clone());
-            res.push(self.cmee(at_cmd));
-            res.push(at!(OK));
-            return Some(res);
+            res.extend(self.creg(at_cmd, tx.clone()));
+            return Some(bytes(res));
+        } else if let Some(answer) = self.power_command(at_cmd, tx.clone()) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.modem_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.sleep_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.rtc_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.tcpip_command(at_cmd, tx.clone()) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.dns_command(at_cmd, tx.clone()) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.bearer_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.http_command(at_cmd, tx.clone()) {
+            return Some([bytes(res), answer].concat());
+        } else if let Some(answer) = self.ftp_command(at_cmd, tx.clone()) {
+            return Some([bytes(res), answer].concat());
+        } else if let Some(answer) = self.ntp_command(at_cmd, tx.clone()) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.cell_command(at_cmd, tx.clone()) {
+            res.extend(answer);
+            return Some(bytes(res));
+        } else if let Some(answer) = self.gnss_command(at_cmd) {
+            res.extend(answer);
+            return Some(bytes(res));
         } else {
-            return Some(vec!["Not Implemendted Command".to_owned() + at_cmd]);
+            return Some(bytes(vec!["Not Implemendted Command".to_owned() + at_cmd]));
         }
     }
 }
@@ -206,73 +359,128 @@ impl Sim868 {
 pub mod sim {
 
     pub mod parse {
-        use crate::sim868::{
-            Sim868, AT_CGMI, AT_CGMM, AT_CGMR, AT_CGSN, AT_CMEE, AT_ECHO, AT_IPR, CME_ERROR, OK,
+        use std::sync::mpsc::Sender;
+
+        use crate::{
+            sim868::{ipr_valid, params, Sim868, AT_CMEE, AT_CREG, AT_ECHO, AT_IPR, ERROR, OK},
+            utils::serial::PortControl,
         };
+
         impl Sim868 {
-            pub fn ipr<'a>(&mut self, line: &'a str) -> &'a str {
-                let op = line.as_bytes()[AT_IPR.len()] as char;
-                if op == '=' {
-                    //set device baudrate
-                    let requested_baudrate = line[AT_IPR.len() + 1..].parse::<usize>().unwrap();
-                    if requested_baudrate == self.configs.baudrate {
-                        return line;
-                    } else {
-                        return CME_ERROR;
-                    }
-                    // if possible, then
-                } else if op == '?' {
-                    // return the baudrate
-                    return "";
-                } else {
-                    return CME_ERROR;
+            /// `AT+IPR=<rate>` answers at the old speed and then switches, 0 auto-bauds
+            pub fn ipr(&mut self, line: &str) -> Vec<String> {
+                let rest = &line[AT_IPR.len()..];
+                if rest.starts_with("=?") {
+                    return vec![
+                        at!("+IPR: (0,1200,2400,4800,9600,19200,38400,57600,115200),(230400,460800)"),
+                        at!(OK),
+                    ];
                 }
+                if rest.starts_with('?') {
+                    return vec![at!(format!("+IPR: {}", self.configs.baudrate)), at!(OK)];
+                }
+                let rate = params(line, AT_IPR)
+                    .first()
+                    .and_then(|r| r.parse::<usize>().ok())
+                    .filter(|r| ipr_valid(*r));
+                let Some(rate) = rate else {
+                    return vec![at!(ERROR)];
+                };
+                self.set_baudrate(rate);
+                vec![at!(OK)]
             }
 
-            pub fn echo(&mut self, line: &str) -> String {
-                let mode = line.as_bytes()[AT_ECHO.len()] as u8 - '0' as u8;
-                if mode == 1 {
-                    self.configs.echo = true;
-                    return at!(OK).to_owned();
+            /// Moves the UART to `rate`, the port thread waits for the output to drain first
+            pub fn set_baudrate(&mut self, rate: usize) {
+                self.configs.baudrate = rate;
+                if let Some(ctrl) = &self.port_ctrl {
+                    let _ = ctrl.send(PortControl::Baud(rate as u32));
                 }
-                return line.to_string();
             }
 
-            pub fn cmee(&mut self, line: &str) -> String {
-                let mode = line.as_bytes()[AT_CMEE.len()] as char;
-                if mode == '=' {
-                    let value = line.as_bytes()[AT_CMEE.len() + 1] as u8 - '0' as u8;
-                    self.configs.cmee = value;
-                    return at!(OK).to_owned();
+            /// `ATE[<value>]`, a bare `ATE` is `ATE0`
+            pub fn echo(&mut self, line: &str) -> String {
+                match line[AT_ECHO.len()..].trim() {
+                    "" | "0" => self.configs.echo = false,
+                    "1" => self.configs.echo = true,
+                    _ => return at!(ERROR),
                 }
-                return mode.to_string();
+                at!(OK)
             }
 
-            pub fn manu_info(&mut self, line: &str) -> String {
-                let result = if line == AT_CGMI {
-                    at!("SIMCOM_Ltd")
-                } else if line == AT_CGMM {
-                    at!("SIMCOM_SIM868")
-                } else if line == AT_CGSN {
-                    at!("86737803397915")
-                } else if line == AT_CGMR {
-                    at!("1418B05Scustome")
-                } else {
-                    at!(CME_ERROR)
-                };
-                result
+            /// `AT+CMEE=<n>`, 0 plain ERROR, 1 numeric and 2 verbose `+CME ERROR`
+            pub fn cmee(&mut self, line: &str) -> Vec<String> {
+                let rest = &line[AT_CMEE.len()..];
+                if rest.starts_with("=?") {
+                    return vec![at!("+CMEE: (0-2)"), at!(OK)];
+                }
+                if rest.starts_with('?') {
+                    return vec![at!(format!("+CMEE: {}", self.configs.cmee)), at!(OK)];
+                }
+                match params(line, AT_CMEE)
+                    .first()
+                    .and_then(|n| n.parse::<u8>().ok())
+                {
+                    Some(n @ 0..=2) => {
+                        self.configs.cmee = n;
+                        vec![at!(OK)]
+                    }
+                    _ => vec![at!(ERROR)],
+                }
             }
-            pub fn creg(&mut self, line: &str) -> String {
-                let mode = line.as_bytes()[AT_CMEE.len()] as char;
-                if mode == '=' {
-                    let value = line.as_bytes()[AT_CMEE.len() + 1] as u8 - '0' as u8;
-                    if value == 1 {
-                        // spawn thread to network registration data
+
+            /// `AT+CREG=<n>`, 1 and 2 report registration changes as `+CREG` URCs
+            pub fn creg(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+                let rest = &line[AT_CREG.len()..];
+                if rest.starts_with("=?") {
+                    return vec![at!("+CREG: (0-2)"), at!(OK)];
+                }
+                if rest.starts_with('?') {
+                    let stat = *self.reg_status.lock().unwrap();
+                    return vec![
+                        at!(format!("+CREG: {},{}", self.configs.creg, stat)),
+                        at!(OK),
+                    ];
+                }
+                match params(line, AT_CREG)
+                    .first()
+                    .and_then(|n| n.parse::<u8>().ok())
+                {
+                    Some(n @ 0..=2) => {
+                        if self.configs.creg == 0 && n > 0 {
+                            self.creg_thread(tx);
+                        }
+                        self.configs.creg = n;
+                        vec![at!(OK)]
                     }
-                    self.configs.cmee = value;
-                    return at!(OK).to_owned();
+                    _ => vec![at!(ERROR)],
+                }
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use crate::sim868::{GnssConfiguration, Sim868};
+
+            #[test]
+            fn cmee_sets_and_reads_back() {
+                let mut sim = Sim868::new(true, GnssConfiguration::default());
+                assert_eq!(sim.cmee("AT+CMEE=2"), vec!["\r\nOK\r\n"]);
+                assert_eq!(sim.cmee("AT+CMEE?"), vec!["\r\n+CMEE: 2\r\n", "\r\nOK\r\n"]);
+                assert_eq!(
+                    sim.cmee("AT+CMEE=?"),
+                    vec!["\r\n+CMEE: (0-2)\r\n", "\r\nOK\r\n"]
+                );
+            }
+
+            #[test]
+            fn cmee_rejects_invalid_values() {
+                let mut sim = Sim868::new(true, GnssConfiguration::default());
+                sim.cmee("AT+CMEE=1");
+                for line in ["AT+CMEE", "AT+CMEE=", "AT+CMEE=x", "AT+CMEE=3", "AT+CMEE=7"] {
+                    assert_eq!(sim.cmee(line), vec!["\r\nERROR\r\n"], "{}", line);
                 }
-                return mode.to_string();
+                assert_eq!(sim.cmee("AT+CMEE?"), vec!["\r\n+CMEE: 1\r\n", "\r\nOK\r\n"]);
             }
         }
     }
@@ -283,13 +491,13 @@ pub mod sim {
         use crate::sim868::Sim868;
 
         impl Sim868 {
-            pub fn creg_thread(&self, tx: Sender<String>) {
+            pub fn creg_thread(&self, tx: Sender<Vec<u8>>) {
                 let shared_reg = self.reg_status.clone();
                 let mut last_status = *shared_reg.lock().unwrap();
                 std::thread::spawn(move || loop {
                     if last_status != *shared_reg.lock().unwrap() {
                         last_status = *shared_reg.lock().unwrap();
-                        tx.send("+CREG: ".to_owned() + &last_status.to_string());
+                        tx.send(("+CREG: ".to_owned() + &last_status.to_string()).into());
                     }
                     std::thread::sleep(Duration::from_secs(5))
                 });
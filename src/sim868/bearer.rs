@@ -0,0 +1,115 @@
+use crate::sim868::{params, Sim868, ERROR, OK};
+
+const AT_SAPBR: &str = "AT+SAPBR";
+
+/// bearer profiles the module offers to its application stacks
+pub const BEARER_PROFILES: usize = 3;
+
+pub const BEARER_CONNECTED: u8 = 1;
+pub const BEARER_CLOSED: u8 = 3;
+
+#[derive(Clone)]
+pub struct BearerProfile {
+    pub contype: String,
+    pub apn: String,
+    pub user: String,
+    pub pwd: String,
+    pub status: u8,
+    pub ip: String,
+}
+
+impl BearerProfile {
+    pub fn new() -> BearerProfile {
+        BearerProfile {
+            contype: "GPRS".to_owned(),
+            apn: String::new(),
+            user: String::new(),
+            pwd: String::new(),
+            status: BEARER_CLOSED,
+            ip: "0.0.0.0".to_owned(),
+        }
+    }
+}
+
+impl Sim868 {
+    /// Bearer profile `cid` when it is open and the radio is on, what the HTTP, FTP and NTP
+    /// stacks run on
+    pub fn open_bearer(&self, cid: usize) -> Option<&BearerProfile> {
+        if !self.radio_on() {
+            return None;
+        }
+        cid.checked_sub(1)
+            .and_then(|i| self.bearers.get(i))
+            .filter(|b| b.status == BEARER_CONNECTED)
+    }
+
+    pub fn bearer_command(&mut self, line: &str) -> Option<Vec<String>> {
+        if !line.starts_with(AT_SAPBR) {
+            return None;
+        }
+        Some(self.sapbr(line))
+    }
+
+    /// `AT+SAPBR=<cmd>,<cid>[,<param>,<value>]`
+    fn sapbr(&mut self, line: &str) -> Vec<String> {
+        let args = params(line, AT_SAPBR);
+        let cmd = args.first().and_then(|a| a.parse::<u8>().ok());
+        let cid = args.get(1).and_then(|a| a.parse::<usize>().ok());
+        let Some(cid) = cid.filter(|c| (1..=BEARER_PROFILES).contains(c)) else {
+            return vec![at!(ERROR)];
+        };
+        let ip = self
+            .config
+            .get("bearer.ip")
+            .unwrap_or("10.176.23.41")
+            .to_owned();
+        let fail_open = self.config.get_or("bearer.fail_open", false) || !self.radio_on();
+        let bearer = &mut self.bearers[cid - 1];
+        match cmd {
+            Some(0) => {
+                if bearer.status != BEARER_CONNECTED {
+                    return vec![at!(ERROR)];
+                }
+                bearer.status = BEARER_CLOSED;
+                bearer.ip = "0.0.0.0".to_owned();
+                vec![at!(OK)]
+            }
+            Some(1) => {
+                if bearer.status == BEARER_CONNECTED || fail_open {
+                    return vec![at!(ERROR)];
+                }
+                bearer.status = BEARER_CONNECTED;
+                bearer.ip = ip;
+                vec![at!(OK)]
+            }
+            Some(2) => vec![
+                at!(format!(
+                    "+SAPBR: {},{},\"{}\"",
+                    cid, bearer.status, bearer.ip
+                )),
+                at!(OK),
+            ],
+            Some(3) => {
+                let (Some(param), Some(value)) = (args.get(2), args.get(3)) else {
+                    return vec![at!(ERROR)];
+                };
+                match param.to_uppercase().as_str() {
+                    "CONTYPE" => bearer.contype = value.clone(),
+                    "APN" => bearer.apn = value.clone(),
+                    "USER" => bearer.user = value.clone(),
+                    "PWD" => bearer.pwd = value.clone(),
+                    _ => return vec![at!(ERROR)],
+                }
+                vec![at!(OK)]
+            }
+            Some(4) => vec![
+                at!(format!(
+                    "+SAPBR:\r\nCONTYPE: {}\r\nAPN: {}\r\nUSER: {}\r\nPWD: {}",
+                    bearer.contype, bearer.apn, bearer.user, bearer.pwd
+                )),
+                at!(OK),
+            ],
+            _ => vec![at!(ERROR)],
+        }
+    }
+}
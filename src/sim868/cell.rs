@@ -0,0 +1,223 @@
+use std::{
+    sync::{atomic::Ordering, mpsc::Sender},
+    time::Duration,
+};
+
+use crate::{
+    sim868::{params, Sim868, ERROR, OK},
+    utils::{
+        geo,
+        random::Random,
+        time::{self, DateTime},
+    },
+};
+
+const AT_CENG: &str = "AT+CENG";
+const AT_CIPGSMLOC: &str = "AT+CIPGSMLOC";
+
+pub const GSMLOC_SUCCESS: u16 = 0;
+pub const GSMLOC_NETWORK_ERROR: u16 = 601;
+
+/// neighbour cells the engineering mode reports at most
+const MAX_NEIGHBOURS: usize = 6;
+
+/// cells used when the configuration gives none
+const DEFAULT_SERVING: &str = "24,44,0,432,11,36,5a2b,0079";
+const DEFAULT_NEIGHBOURS: [&str; 2] =
+    ["18,31,0,432,11,45,5a2c,0079", "37,24,0,432,11,12,6b11,0079"];
+
+/// A GSM cell as AT+CENG describes it, `cell_id` and `lac` are shown in hex
+pub struct Cell {
+    pub arfcn: u16,
+    pub rxl: u8,
+    pub rxq: u8,
+    pub mcc: u16,
+    pub mnc: u16,
+    pub bsic: u8,
+    pub cell_id: u32,
+    pub lac: u32,
+}
+
+impl Cell {
+    /// `<arfcn>,<rxl>,<rxq>,<mcc>,<mnc>,<bsic>,<cellid>,<lac>` with the cell id and LAC in hex
+    fn parse(text: &str) -> Option<Cell> {
+        let fields: Vec<&str> = text.split(',').map(|f| f.trim()).collect();
+        Some(Cell {
+            arfcn: fields.first()?.parse().ok()?,
+            rxl: fields.get(1)?.parse().ok()?,
+            rxq: fields.get(2)?.parse().ok()?,
+            mcc: fields.get(3)?.parse().ok()?,
+            mnc: fields.get(4)?.parse().ok()?,
+            bsic: fields.get(5)?.parse().ok()?,
+            cell_id: u32::from_str_radix(fields.get(6)?, 16).ok()?,
+            lac: u32::from_str_radix(fields.get(7)?, 16).ok()?,
+        })
+    }
+}
+
+/// Engineering mode set by `AT+CENG=<mode>,<Ncell>`
+pub struct Engineering {
+    pub mode: u8,
+    pub ncell: bool,
+}
+
+impl Engineering {
+    pub fn new() -> Engineering {
+        Engineering {
+            mode: 0,
+            ncell: false,
+        }
+    }
+}
+
+impl Sim868 {
+    /// Registered on the home network or roaming, what the cell table and GSM location need
+    fn registered(&self) -> bool {
+        self.radio_on() && matches!(*self.reg_status.lock().unwrap(), 1 | 5)
+    }
+
+    /// The serving cell and the neighbours from `cell.serving` and `cell.neighbour.<1-6>`
+    fn cell_table(&self) -> (Cell, Vec<Cell>) {
+        let serving = self
+            .config
+            .get("cell.serving")
+            .and_then(Cell::parse)
+            .or_else(|| Cell::parse(DEFAULT_SERVING))
+            .unwrap();
+        let configured: Vec<Cell> = (1..=MAX_NEIGHBOURS)
+            .filter_map(|i| self.config.get(&format!("cell.neighbour.{}", i)))
+            .filter_map(Cell::parse)
+            .collect();
+        let neighbours = if configured.is_empty() {
+            DEFAULT_NEIGHBOURS
+                .iter()
+                .filter_map(|c| Cell::parse(c))
+                .collect()
+        } else {
+            configured
+        };
+        (serving, neighbours)
+    }
+
+    pub fn cell_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<String>> {
+        if line.starts_with(AT_CENG) {
+            Some(self.ceng(line))
+        } else if line.starts_with(AT_CIPGSMLOC) {
+            Some(self.cipgsmloc(line, tx))
+        } else {
+            None
+        }
+    }
+
+    fn ceng(&mut self, line: &str) -> Vec<String> {
+        if line[AT_CENG.len()..].starts_with('?') {
+            let mut answer = vec![at!(format!(
+                "+CENG: {},{}",
+                self.engineering.mode, self.engineering.ncell as u8
+            ))];
+            if self.engineering.mode != 0 {
+                answer.extend(self.cell_report());
+            }
+            answer.push(at!(OK));
+            return answer;
+        }
+        let args = params(line, AT_CENG);
+        let Some(mode) = args
+            .first()
+            .and_then(|m| m.parse::<u8>().ok())
+            .filter(|m| *m <= 4)
+        else {
+            return vec![at!(ERROR)];
+        };
+        let ncell = match args.get(1).map(|n| n.as_str()) {
+            None => self.engineering.ncell,
+            Some("0") => false,
+            Some("1") => true,
+            _ => return vec![at!(ERROR)],
+        };
+        self.engineering = Engineering { mode, ncell };
+        vec![at!(OK)]
+    }
+
+    /// Serving cell line and one line per neighbour, nothing but an empty serving cell when unregistered
+    fn cell_report(&self) -> Vec<String> {
+        if !self.registered() {
+            return vec![at!("+CENG: 0,\"0000,00,00,000,00,00,0000,00,00,0000,255\"")];
+        }
+        let (serving, neighbours) = self.cell_table();
+        let mut report = vec![at!(format!(
+            "+CENG: 0,\"{:04},{:02},{:02},{:03},{:02},{:02},{:04x},{:02},05,{:04x},0\"",
+            serving.arfcn,
+            serving.rxl,
+            serving.rxq,
+            serving.mcc,
+            serving.mnc,
+            serving.bsic,
+            serving.cell_id,
+            serving.rxl.saturating_sub(10),
+            serving.lac
+        ))];
+        for (i, cell) in neighbours.iter().enumerate() {
+            let fields = if self.engineering.ncell {
+                format!(
+                    "{:04},{:02},{:02},{:04x},{:03},{:02},{:04x}",
+                    cell.arfcn, cell.rxl, cell.bsic, cell.cell_id, cell.mcc, cell.mnc, cell.lac
+                )
+            } else {
+                format!("{:04},{:02},{:02}", cell.arfcn, cell.rxl, cell.bsic)
+            };
+            report.push(at!(format!("+CENG: {},\"{}\"", i + 1, fields)));
+        }
+        report
+    }
+
+    /// `AT+CIPGSMLOC=1,<cid>` gives the position around the simulated one, `2,<cid>` only the time
+    fn cipgsmloc(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        if line[AT_CIPGSMLOC.len()..].starts_with("=?") {
+            return vec![at!("+CIPGSMLOC: (1,2),(1-3)"), at!(OK)];
+        }
+        let args = params(line, AT_CIPGSMLOC);
+        let kind = args.first().and_then(|t| t.parse::<u8>().ok());
+        let cid = args.get(1).and_then(|c| c.parse::<usize>().ok());
+        let (Some(kind @ 1..=2), Some(cid)) = (kind, cid) else {
+            return vec![at!(ERROR)];
+        };
+        let code = match self.config.get("gsmloc.fail") {
+            Some(code) => code.parse().unwrap_or(GSMLOC_NETWORK_ERROR),
+            None if !self.registered() || self.open_bearer(cid).is_none() => GSMLOC_NETWORK_ERROR,
+            None => GSMLOC_SUCCESS,
+        };
+        let position = self.gnss.lock().unwrap().true_position();
+        let error = self.config.get_or("gsmloc.error_m", 300.0);
+        let delay = self.config.get_or("gsmloc.delay_ms", 2000);
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(delay));
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                return;
+            }
+            let now = DateTime::from_unix(time::now());
+            let date = format!(
+                "{:04}/{:02}/{:02},{:02}:{:02}:{:02}",
+                now.year, now.month, now.day, now.hour, now.minute, now.second
+            );
+            let urc = if code != GSMLOC_SUCCESS {
+                format!("+CIPGSMLOC: {}", code)
+            } else if kind == 1 {
+                let mut random = Random::new();
+                // uniform over the disc around the true position
+                let distance = error * (random.below(1_000_001) as f64 / 1e6).sqrt();
+                let bearing = random.below(360) as f64;
+                let (latitude, longitude) =
+                    geo::offset(position.latitude, position.longitude, distance, bearing);
+                format!("+CIPGSMLOC: 0,{:.6},{:.6},{}", longitude, latitude, date)
+            } else {
+                format!("+CIPGSMLOC: 0,{}", date)
+            };
+            let _ = tx.send(at!(urc).into());
+            let _ = tx.send(at!(OK).into());
+        });
+        vec![]
+    }
+}
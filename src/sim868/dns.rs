@@ -0,0 +1,187 @@
+use std::{
+    net::{IpAddr, ToSocketAddrs},
+    sync::{atomic::Ordering, mpsc::Sender},
+    time::Duration,
+};
+
+use crate::{
+    config::Config,
+    sim868::{params, Sim868, ERROR, OK},
+    utils::random::Random,
+};
+
+const AT_CDNSCFG: &str = "AT+CDNSCFG";
+const AT_CDNSGIP: &str = "AT+CDNSGIP";
+const AT_CIPPING: &str = "AT+CIPPING";
+
+/// DNS common error, answered for names missing from the host table
+pub const DNS_COMMON_ERROR: u8 = 8;
+/// reply time the module reports for a ping that timed out, in 100 ms units
+const PING_TIMEOUT_REPLY: u64 = 600;
+
+pub struct Dns {
+    pub primary: String,
+    pub secondary: String,
+}
+
+impl Dns {
+    pub fn new() -> Dns {
+        Dns {
+            primary: "8.8.8.8".to_owned(),
+            secondary: "8.8.4.4".to_owned(),
+        }
+    }
+}
+
+/// Resolves `host` the way the emulated network does: scripted failures first, then the
+/// `dns.host.<name>` table and, when `dns.fallthrough` is set, the PC's own resolver.
+pub fn resolve(config: &Config, host: &str) -> Result<Vec<String>, u8> {
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(vec![host.to_owned()]);
+    }
+    if let Some(code) = config.get(&format!("dns.fail.{}", host)) {
+        return Err(code.parse().unwrap_or(DNS_COMMON_ERROR));
+    }
+    if let Some(code) = config.get("dns.error") {
+        return Err(code.parse().unwrap_or(DNS_COMMON_ERROR));
+    }
+    if let Some(ips) = config.get(&format!("dns.host.{}", host)) {
+        // an entry without addresses resolves to nothing, like a name missing from the table
+        let ips: Vec<String> = ips.split_whitespace().map(|ip| ip.to_owned()).collect();
+        return if ips.is_empty() {
+            Err(DNS_COMMON_ERROR)
+        } else {
+            Ok(ips)
+        };
+    }
+    if config.get_or("dns.fallthrough", false) {
+        if let Ok(addrs) = (host, 0).to_socket_addrs() {
+            let ips: Vec<String> = addrs
+                .filter(|a| a.is_ipv4())
+                .map(|a| a.ip().to_string())
+                .collect();
+            if !ips.is_empty() {
+                return Ok(ips);
+            }
+        }
+    }
+    Err(DNS_COMMON_ERROR)
+}
+
+impl Sim868 {
+    pub fn dns_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<String>> {
+        let answer = if line.starts_with(AT_CDNSCFG) {
+            self.cdnscfg(line)
+        } else if line.starts_with(AT_CDNSGIP) {
+            self.cdnsgip(line, tx)
+        } else if line.starts_with(AT_CIPPING) {
+            self.cipping(line, tx)
+        } else {
+            return None;
+        };
+        Some(answer)
+    }
+
+    fn cdnscfg(&mut self, line: &str) -> Vec<String> {
+        if line[AT_CDNSCFG.len()..].starts_with('?') {
+            return vec![
+                at!(format!(
+                    "PrimaryDns: {}\r\nSecondaryDns: {}",
+                    self.dns.primary, self.dns.secondary
+                )),
+                at!(OK),
+            ];
+        }
+        let args = params(line, AT_CDNSCFG);
+        let Some(primary) = args.first().filter(|ip| ip.parse::<IpAddr>().is_ok()) else {
+            return vec![at!(ERROR)];
+        };
+        self.dns.primary = primary.clone();
+        if let Some(secondary) = args.get(1) {
+            if secondary.parse::<IpAddr>().is_err() {
+                return vec![at!(ERROR)];
+            }
+            self.dns.secondary = secondary.clone();
+        }
+        vec![at!(OK)]
+    }
+
+    fn cdnsgip(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let host = params(line, AT_CDNSGIP).into_iter().next();
+        let Some(host) = host.filter(|_| self.radio_on()) else {
+            return vec![at!(ERROR)];
+        };
+        let config = self.config.clone();
+        let delay = self.config.get_or("dns.delay_ms", 300);
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(delay));
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                return;
+            }
+            let urc = match resolve(&config, &host) {
+                Ok(ips) if !ips.is_empty() => {
+                    let ips: Vec<String> =
+                        ips.iter().take(2).map(|ip| format!("\"{}\"", ip)).collect();
+                    format!("+CDNSGIP: 1,\"{}\",{}", host, ips.join(","))
+                }
+                Ok(_) => format!("+CDNSGIP: 0,{}", DNS_COMMON_ERROR),
+                Err(code) => format!("+CDNSGIP: 0,{}", code),
+            };
+            let _ = tx.send(at!(urc).into());
+        });
+        vec![at!(OK)]
+    }
+
+    /// `AT+CIPPING=<addr>[,<retry>[,<size>[,<timeout>[,<ttl>]]]]`, replies follow the
+    /// `ping.latency_ms`, `ping.jitter_ms` and `ping.loss_percent` profile
+    fn cipping(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let args = params(line, AT_CIPPING);
+        let Some(host) = args.first().filter(|_| self.radio_on()) else {
+            return vec![at!(ERROR)];
+        };
+        let arg = |i: usize, default: u64| {
+            args.get(i)
+                .and_then(|a| a.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        let (retries, timeout, ttl) = (arg(1, 4), arg(3, 100), arg(4, 64));
+        let Some(ip) = resolve(&self.config, host)
+            .ok()
+            .and_then(|ips| ips.into_iter().next())
+        else {
+            return vec![at!(ERROR)];
+        };
+        let latency = self.config.get_or("ping.latency_ms", 80u64);
+        let jitter = self.config.get_or("ping.jitter_ms", 20u64);
+        let loss = self.config.get_or("ping.loss_percent", 0u64);
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let mut random = Random::new();
+            for reply in 1..=retries {
+                let rtt = latency + random.below(jitter + 1);
+                let urc = if random.chance(loss) || rtt >= timeout * 100 {
+                    std::thread::sleep(Duration::from_millis(timeout * 100));
+                    format!("+CIPPING: {},\"{}\",{},255", reply, ip, PING_TIMEOUT_REPLY)
+                } else {
+                    std::thread::sleep(Duration::from_millis(rtt));
+                    format!(
+                        "+CIPPING: {},\"{}\",{},{}",
+                        reply,
+                        ip,
+                        rtt.div_ceil(100),
+                        ttl
+                    )
+                };
+                if power_cycle.load(Ordering::SeqCst) != cycle {
+                    return;
+                }
+                let _ = tx.send(at!(urc).into());
+            }
+            let _ = tx.send(at!(OK).into());
+        });
+        vec![]
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::{atomic::Ordering, mpsc::Sender},
+    time::Duration,
+};
+
+use crate::{
+    sim868::{bytes, params, PendingInput, Sim868, ERROR, OK},
+    utils::serial::PortControl,
+};
+
+const AT_FTPCID: &str = "AT+FTPCID";
+const AT_FTPSERV: &str = "AT+FTPSERV";
+const AT_FTPPORT: &str = "AT+FTPPORT";
+const AT_FTPUN: &str = "AT+FTPUN";
+const AT_FTPPW: &str = "AT+FTPPW";
+const AT_FTPGETNAME: &str = "AT+FTPGETNAME";
+const AT_FTPGETPATH: &str = "AT+FTPGETPATH";
+const AT_FTPPUTNAME: &str = "AT+FTPPUTNAME";
+const AT_FTPPUTPATH: &str = "AT+FTPPUTPATH";
+const AT_FTPPUTOPT: &str = "AT+FTPPUTOPT";
+const AT_FTPGET: &str = "AT+FTPGET";
+const AT_FTPPUT: &str = "AT+FTPPUT";
+const AT_FTPSIZE: &str = "AT+FTPSIZE";
+const AT_FTPDELE: &str = "AT+FTPDELE";
+const AT_FTPSTATE: &str = "AT+FTPSTATE";
+
+pub const FTP_NETWORK_ERROR: u8 = 61;
+pub const FTP_USER_ERROR: u8 = 71;
+pub const FTP_PASSWORD_ERROR: u8 = 72;
+pub const FTP_OPERATE_ERROR: u8 = 77;
+
+/// largest chunk AT+FTPPUT=2 accepts, announced in `+FTPPUT: 1,1,<maxlength>`
+const FTP_PUT_MAX: usize = 1360;
+const FTP_GET_MAX: usize = 1460;
+
+pub struct Ftp {
+    pub cid: usize,
+    pub server: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub get_name: String,
+    pub get_path: String,
+    pub put_name: String,
+    pub put_path: String,
+    pub put_opt: String,
+    /// file being read by AT+FTPGET=2 and how much of it went to the host
+    download: Option<(Vec<u8>, usize)>,
+    /// file being written by AT+FTPPUT=2
+    upload: Option<PathBuf>,
+}
+
+impl Ftp {
+    pub fn new() -> Ftp {
+        Ftp {
+            cid: 1,
+            server: String::new(),
+            port: 21,
+            user: String::new(),
+            password: String::new(),
+            get_name: String::new(),
+            get_path: "/".to_owned(),
+            put_name: String::new(),
+            put_path: "/".to_owned(),
+            put_opt: "STOR".to_owned(),
+            download: None,
+            upload: None,
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.download.is_some() || self.upload.is_some()
+    }
+}
+
+impl Sim868 {
+    /// `<ftp.root>/<path>/<name>`, anything climbing out of the root is refused
+    fn ftp_file(&self, path: &str, name: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/')).join(name);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        Some(Path::new(self.config.get("ftp.root").unwrap_or("ftp")).join(relative))
+    }
+
+    /// Result code every FTP session starts with, `0` when the operation may go on
+    fn ftp_session_error(&self, operation: &str) -> u8 {
+        if let Some(code) = self.config.get(&format!("ftp.fail.{}", operation)) {
+            return code.parse().unwrap_or(FTP_NETWORK_ERROR);
+        }
+        if let Some(code) = self.config.get("ftp.fail") {
+            return code.parse().unwrap_or(FTP_NETWORK_ERROR);
+        }
+        if self.open_bearer(self.ftp.cid).is_none() || self.ftp.server.is_empty() {
+            return FTP_NETWORK_ERROR;
+        }
+        if self
+            .config
+            .get("ftp.user")
+            .is_some_and(|u| u != self.ftp.user)
+        {
+            return FTP_USER_ERROR;
+        }
+        if self
+            .config
+            .get("ftp.password")
+            .is_some_and(|p| p != self.ftp.password)
+        {
+            return FTP_PASSWORD_ERROR;
+        }
+        0
+    }
+
+    /// Sends the session results the way the module does, a while after the command's OK
+    fn ftp_urc(&self, urcs: Vec<String>, tx: Sender<Vec<u8>>) {
+        let delay = self.config.get_or("ftp.delay_ms", 500);
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(delay));
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                return;
+            }
+            for urc in urcs {
+                let _ = tx.send(at!(urc).into());
+            }
+        });
+    }
+
+    /// `AT+FTP<param>?` answers the setting, strings in quotes
+    fn ftp_setting(&self, command: &str) -> Option<String> {
+        let ftp = &self.ftp;
+        let value = match command {
+            AT_FTPCID => ftp.cid.to_string(),
+            AT_FTPPORT => ftp.port.to_string(),
+            AT_FTPSERV => format!("\"{}\"", ftp.server),
+            AT_FTPUN => format!("\"{}\"", ftp.user),
+            AT_FTPPW => format!("\"{}\"", ftp.password),
+            AT_FTPGETNAME => format!("\"{}\"", ftp.get_name),
+            AT_FTPGETPATH => format!("\"{}\"", ftp.get_path),
+            AT_FTPPUTNAME => format!("\"{}\"", ftp.put_name),
+            AT_FTPPUTPATH => format!("\"{}\"", ftp.put_path),
+            AT_FTPPUTOPT => format!("\"{}\"", ftp.put_opt),
+            _ => return None,
+        };
+        Some(format!("{}: {}", &command[2..], value))
+    }
+
+    pub fn ftp_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+        if !line.starts_with("AT+FTP") {
+            return None;
+        }
+        let command = line.split(['=', '?']).next().unwrap_or(line).trim();
+        let rest = line[command.len()..].trim();
+        if rest == "?" {
+            let setting = self.ftp_setting(command)?;
+            return Some(bytes(vec![at!(setting), at!(OK)]));
+        }
+        if rest == "=?" {
+            self.ftp_setting(command)?;
+            return Some(bytes(vec![at!(OK)]));
+        }
+        if command == AT_FTPGET {
+            return Some(self.ftpget(line, tx));
+        }
+        let value = params(line, command).into_iter().next();
+        let ftp = &mut self.ftp;
+        let setting = match command {
+            AT_FTPCID => value.and_then(|v| v.parse().ok()).map(|cid| ftp.cid = cid),
+            AT_FTPSERV => value.map(|v| ftp.server = v),
+            AT_FTPPORT => value
+                .and_then(|v| v.parse().ok())
+                .map(|port| ftp.port = port),
+            AT_FTPUN => value.map(|v| ftp.user = v),
+            AT_FTPPW => value.map(|v| ftp.password = v),
+            AT_FTPGETNAME => value.map(|v| ftp.get_name = v),
+            AT_FTPGETPATH => value.map(|v| ftp.get_path = v),
+            AT_FTPPUTNAME => value.map(|v| ftp.put_name = v),
+            AT_FTPPUTPATH => value.map(|v| ftp.put_path = v),
+            AT_FTPPUTOPT => value
+                .filter(|v| v == "STOR" || v == "APPE")
+                .map(|v| ftp.put_opt = v),
+            AT_FTPPUT => return Some(bytes(self.ftpput(line, tx))),
+            AT_FTPSIZE => return Some(bytes(self.ftpsize(tx))),
+            AT_FTPDELE => return Some(bytes(self.ftpdele(tx))),
+            AT_FTPSTATE => {
+                return Some(bytes(vec![
+                    at!(format!("+FTPSTATE: {}", self.ftp.is_busy() as u8)),
+                    at!(OK),
+                ]))
+            }
+            _ => return None,
+        };
+        Some(bytes(match setting {
+            Some(()) => vec![at!(OK)],
+            None => vec![at!(ERROR)],
+        }))
+    }
+
+    /// `AT+FTPGET=1` opens the download, `AT+FTPGET=2,<reqlength>` reads the next chunk of it
+    /// byte for byte
+    fn ftpget(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<Vec<u8>> {
+        let args = params(line, AT_FTPGET);
+        match args.first().map(|a| a.as_str()) {
+            Some("1") => {
+                if self.ftp.is_busy() {
+                    return bytes(vec![at!(ERROR)]);
+                }
+                let mut code = self.ftp_session_error("get");
+                if code == 0 {
+                    match self
+                        .ftp_file(&self.ftp.get_path, &self.ftp.get_name)
+                        .and_then(|f| fs::read(f).ok())
+                    {
+                        Some(content) => self.ftp.download = Some((content, 0)),
+                        None => code = FTP_OPERATE_ERROR,
+                    }
+                }
+                let urcs = match &self.ftp.download {
+                    _ if code != 0 => vec![format!("+FTPGET: 1,{}", code)],
+                    // nothing to read, the session is over as soon as it opened
+                    Some((content, _)) if content.is_empty() => {
+                        self.ftp.download = None;
+                        vec!["+FTPGET: 1,1".to_owned(), "+FTPGET: 1,0".to_owned()]
+                    }
+                    _ => vec!["+FTPGET: 1,1".to_owned()],
+                };
+                self.ftp_urc(urcs, tx);
+                bytes(vec![at!(OK)])
+            }
+            Some("2") => {
+                let Some(length) = args
+                    .get(1)
+                    .and_then(|a| a.parse::<usize>().ok())
+                    .filter(|l| (1..=FTP_GET_MAX).contains(l))
+                else {
+                    return bytes(vec![at!(ERROR)]);
+                };
+                let Some((content, sent)) = self.ftp.download.as_mut() else {
+                    return bytes(vec![at!(ERROR)]);
+                };
+                let end = (*sent + length).min(content.len());
+                let mut answer = vec![
+                    at!(format!("+FTPGET: 2,{}", end - *sent)).into_bytes(),
+                    content[*sent..end].to_vec(),
+                    at!(OK).into_bytes(),
+                ];
+                *sent = end;
+                if end == content.len() {
+                    self.ftp.download = None;
+                    answer.push(at!("+FTPGET: 1,0").into_bytes());
+                }
+                answer
+            }
+            _ => bytes(vec![at!(ERROR)]),
+        }
+    }
+
+    /// `AT+FTPPUT=1` opens the upload, `AT+FTPPUT=2,<len>` sends a chunk and `AT+FTPPUT=2,0` ends it
+    fn ftpput(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let args = params(line, AT_FTPPUT);
+        match args.first().map(|a| a.as_str()) {
+            Some("1") => {
+                if self.ftp.is_busy() {
+                    return vec![at!(ERROR)];
+                }
+                let mut code = self.ftp_session_error("put");
+                if code == 0 {
+                    let file = self.ftp_file(&self.ftp.put_path, &self.ftp.put_name);
+                    let opened = file.as_ref().is_some_and(|f| {
+                        OpenOptions::new()
+                            .create(true)
+                            .write(true)
+                            .append(self.ftp.put_opt == "APPE")
+                            .truncate(self.ftp.put_opt == "STOR")
+                            .open(f)
+                            .is_ok()
+                    });
+                    if opened {
+                        self.ftp.upload = file;
+                    } else {
+                        code = FTP_OPERATE_ERROR;
+                    }
+                }
+                let urc = if code == 0 {
+                    format!("+FTPPUT: 1,1,{}", FTP_PUT_MAX)
+                } else {
+                    format!("+FTPPUT: 1,{}", code)
+                };
+                self.ftp_urc(vec![urc], tx);
+                vec![at!(OK)]
+            }
+            Some("2") => {
+                if self.ftp.upload.is_none() {
+                    return vec![at!(ERROR)];
+                }
+                match args.get(1).and_then(|a| a.parse::<usize>().ok()) {
+                    Some(0) => {
+                        self.ftp.upload = None;
+                        vec![at!(OK), at!("+FTPPUT: 1,0")]
+                    }
+                    Some(length) if length <= FTP_PUT_MAX => {
+                        self.expect_data(PendingInput::FtpPut, PortControl::ReadExact(length));
+                        vec![at!(format!("+FTPPUT: 2,{}", length))]
+                    }
+                    _ => vec![at!(ERROR)],
+                }
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    pub fn ftpput_data(&mut self, data: &[u8]) -> Vec<String> {
+        let written = self.ftp.upload.as_ref().is_some_and(|f| {
+            OpenOptions::new()
+                .append(true)
+                .open(f)
+                .and_then(|mut file| file.write_all(data))
+                .is_ok()
+        });
+        if written {
+            vec![at!(OK), at!(format!("+FTPPUT: 1,1,{}", FTP_PUT_MAX))]
+        } else {
+            self.ftp.upload = None;
+            vec![at!(OK), at!(format!("+FTPPUT: 1,{}", FTP_OPERATE_ERROR))]
+        }
+    }
+
+    fn ftpsize(&mut self, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let mut code = self.ftp_session_error("size");
+        let mut size = 0;
+        if code == 0 {
+            match self
+                .ftp_file(&self.ftp.get_path, &self.ftp.get_name)
+                .and_then(|f| fs::metadata(f).ok())
+            {
+                Some(meta) if meta.is_file() => size = meta.len(),
+                _ => code = FTP_OPERATE_ERROR,
+            }
+        }
+        self.ftp_urc(vec![format!("+FTPSIZE: 1,{},{}", code, size)], tx);
+        vec![at!(OK)]
+    }
+
+    fn ftpdele(&mut self, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let mut code = self.ftp_session_error("dele");
+        if code == 0
+            && self
+                .ftp_file(&self.ftp.get_path, &self.ftp.get_name)
+                .is_none_or(|f| fs::remove_file(f).is_err())
+        {
+            code = FTP_OPERATE_ERROR;
+        }
+        self.ftp_urc(vec![format!("+FTPDELE: 1,{}", code)], tx);
+        vec![at!(OK)]
+    }
+}
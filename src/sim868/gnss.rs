@@ -0,0 +1,541 @@
+use std::sync::{mpsc::Sender, Arc, Mutex};
+
+use crate::{
+    config::Config,
+    sim868::{params, GnssConfig, Sim868, ERROR, OK},
+    utils::time::{self, DateTime},
+};
+
+mod acquisition;
+mod agps;
+mod constellation;
+mod injection;
+mod nmea;
+mod pmtk;
+mod track;
+mod uart;
+
+pub use acquisition::{Acquisition, Signal, StartMode};
+pub use agps::Epo;
+pub use constellation::{Satellite, System};
+pub use injection::{Deviation, Injection, Scenario};
+pub use nmea::NmeaLog;
+pub use track::{Track, TrackSample};
+pub use uart::UartMessage;
+
+const AT_CGNSPWR: &str = "AT+CGNSPWR";
+const AT_CGNSINF: &str = "AT+CGNSINF";
+const AT_CGNSURC: &str = "AT+CGNSURC";
+const AT_CGNSSEQ: &str = "AT+CGNSSEQ";
+const AT_CGNSTST: &str = "AT+CGNSTST";
+const AT_CGNSRST: &str = "AT+CGNSRST";
+const AT_CGNSIPR: &str = "AT+CGNSIPR";
+const AT_CGNSCMD: &str = "AT+CGNSCMD";
+const AT_CGNSSAV: &str = "AT+CGNSSAV";
+const AT_CGNSCHK: &str = "AT+CGNSCHK";
+const AT_CGNSAID: &str = "AT+CGNSAID";
+
+/// `<type>` of the EPO file in AT+CGNSSAV and AT+CGNSCHK
+const EPO_FILE: &str = "3";
+/// `<mode>` of AT+CGNSAID that loads the EPO file
+const EPO_AID: &str = "31";
+
+/// NMEA sentences AT+CGNSSEQ accepts as the last one of an output sequence
+const NMEA_SEQUENCES: [&str; 4] = ["GGA", "GSA", "GSV", "RMC"];
+
+/// The GNSS engine of the module, every GNSS command and URC reads from this one model
+pub struct GnssConfiguration {
+    pub urc: u8,
+    pub urc_enabled: bool,
+    pub power: bool,
+    pub seq: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// MSL altitude in meters
+    pub altitude: f64,
+    /// speed over ground in km/h
+    pub speed: f64,
+    /// course over ground in degrees
+    pub course: f64,
+    /// satellites above the horizon once the receiver has settled
+    pub sky: Vec<Satellite>,
+    /// route replayed instead of the static position
+    pub track: Option<Track>,
+    /// NMEA sentences are sent to the port on every fix, `AT+CGNSTST=1`
+    pub nmea_output: bool,
+    /// recording sent instead of the generated sentences
+    pub nmea_log: Option<NmeaLog>,
+    /// TTFF and fix loss, the sky fields above are what it settles to
+    pub acquisition: Acquisition,
+    /// time between fixes, PMTK220
+    pub fix_interval_ms: u64,
+    /// how often each sentence type is sent in fixes, PMTK314
+    pub sentence_rates: Vec<(String, u8)>,
+    /// PMTK161 stops the engine until the next command
+    pub standby: bool,
+    /// answers to AT+CGNSCMD, sent with the next fix
+    pub pmtk_replies: Vec<String>,
+    /// the separate GNSS UART, when `gnss.port` exposes it
+    pub uart: Option<Sender<UartMessage>>,
+    pub uart_baud: u32,
+    /// EPO file saved by AT+CGNSSAV, it stays over GNSS power cycles
+    pub epo: Option<Epo>,
+    /// `gnss.epo_validity_h`, for assistance data that is not an MTK EPO file
+    pub epo_validity_h: f64,
+    /// glitches, freezes and forced speeds laid over the true position
+    pub deviation: Deviation,
+    /// injections of `gnss.scenario` still to come
+    pub scenario: Option<Scenario>,
+    /// wakes the GNSS worker thread when a setting it schedules by changes
+    pub tx: Option<Sender<GnssConfig>>,
+}
+
+impl GnssConfiguration {
+    pub fn default() -> GnssConfiguration {
+        GnssConfiguration {
+            urc: 5,
+            urc_enabled: false,
+            power: false,
+            seq: "RMC".to_owned(),
+            latitude: 35.715298,
+            longitude: 51.404343,
+            altitude: 1190.0,
+            speed: 0.0,
+            course: 0.0,
+            sky: constellation::default_sky(),
+            track: None,
+            nmea_output: false,
+            nmea_log: None,
+            acquisition: Acquisition::new(),
+            fix_interval_ms: 1000,
+            sentence_rates: pmtk::default_sentence_rates(),
+            standby: false,
+            pmtk_replies: vec![],
+            uart: None,
+            uart_baud: 115200,
+            epo: None,
+            epo_validity_h: 72.0,
+            deviation: Deviation::default(),
+            scenario: None,
+            tx: None,
+        }
+    }
+
+    /// Default model with the static position taken from `gnss.latitude`, `gnss.longitude` and `gnss.altitude`,
+    /// or the route of `gnss.track` when one is given
+    pub fn from_config(config: &Config) -> GnssConfiguration {
+        let mut gnss = GnssConfiguration::default();
+        gnss.latitude = config.get_or("gnss.latitude", gnss.latitude);
+        gnss.longitude = config.get_or("gnss.longitude", gnss.longitude);
+        gnss.altitude = config.get_or("gnss.altitude", gnss.altitude);
+        if let Some(path) = config.get("gnss.track") {
+            gnss.track = Track::load(path, config.get_or("gnss.track_speed_kmh", 50.0));
+            if let Some(track) = gnss.track.as_mut() {
+                track.looped = config.get_or("gnss.track_loop", true);
+                track.set_rate(config.get_or("gnss.track_rate", 1.0));
+            }
+        }
+        gnss.nmea_log = config.get("gnss.nmea_log").and_then(NmeaLog::load);
+        gnss.acquisition = Acquisition::from_config(config);
+        if let Some(sky) = config.get("gnss.sky").and_then(constellation::load_sky) {
+            gnss.sky = sky;
+        }
+        gnss.uart_baud = config.get_or("gnss.baud", gnss.uart_baud);
+        gnss.epo_validity_h = config.get_or("gnss.epo_validity_h", gnss.epo_validity_h);
+        gnss.epo = config
+            .get("gnss.epo_file")
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| Epo::parse(&data, time::now(), gnss.epo_validity_h));
+        gnss.scenario = config.get("gnss.scenario").and_then(Scenario::load);
+        gnss
+    }
+
+    /// Where the receiver really is, from the track when one is playing
+    pub fn true_position(&self) -> TrackSample {
+        match &self.track {
+            Some(track) => track.sample(),
+            None => TrackSample {
+                latitude: self.latitude,
+                longitude: self.longitude,
+                altitude: self.altitude,
+                speed: self.speed,
+                course: self.course,
+            },
+        }
+    }
+
+    /// Position the receiver reports, the true one with the injections in effect
+    pub fn position(&self) -> TrackSample {
+        self.deviation.apply(self.true_position())
+    }
+
+    pub fn inject(&mut self, injection: Injection) {
+        match injection {
+            Injection::Teleport {
+                latitude,
+                longitude,
+            } => {
+                self.track = None;
+                self.latitude = latitude;
+                self.longitude = longitude;
+                self.speed = 0.0;
+                self.deviation.frozen = None;
+            }
+            Injection::Jump {
+                meters,
+                bearing,
+                seconds,
+            } => self.deviation.jump = Some((meters, bearing, time::now() + seconds)),
+            Injection::Freeze => self.deviation.frozen = Some(self.position()),
+            Injection::Unfreeze => self.deviation.frozen = None,
+            Injection::Speed { kmh, seconds } => {
+                let until = (seconds > 0.0).then(|| time::now() + seconds);
+                self.deviation.speed = Some((kmh, until));
+            }
+            Injection::Clear => self.deviation = Deviation::default(),
+        }
+    }
+
+    /// Applies the scenario steps that came due
+    pub fn run_scenario(&mut self) {
+        let due = self.scenario.as_mut().map(|s| s.due()).unwrap_or_default();
+        for injection in due {
+            self.inject(injection);
+        }
+    }
+
+    pub fn set_tx(&mut self, tx: Sender<GnssConfig>) {
+        self.tx = Some(tx);
+    }
+
+    /// Tells the worker thread, if one runs, about a change
+    pub fn notify(&self, change: GnssConfig) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(change);
+        }
+    }
+
+    /// Powering up starts an acquisition, hot, warm or cold depending on the last fix
+    pub fn set_power(&mut self, on: bool) {
+        if on && !self.power {
+            let mode = self.acquisition.power_up_mode();
+            self.acquisition.start(mode);
+        } else if !on && self.power {
+            let fixed = self.has_fix();
+            self.acquisition.stop(fixed);
+        }
+        if on != self.power {
+            self.notify(GnssConfig::Power(on));
+        }
+        self.power = on;
+    }
+
+    pub fn has_fix(&self) -> bool {
+        self.power && self.signal().fix
+    }
+
+    /// Satellites, DOPs and signal strength the receiver reports right now
+    pub fn signal(&self) -> Signal {
+        self.acquisition.signal(&self.sky)
+    }
+
+    /// What the engine sends to the port on its `fix`-th fix: the NMEA sentences when
+    /// `AT+CGNSTST=1`, with the `+UGNSINF` report, when it is due, after the sentence chosen by
+    /// `AT+CGNSSEQ`. The GNSS UART gets the sentences whatever AT+CGNSTST says.
+    pub fn output(&mut self, fix: u64, report: bool) -> Vec<String> {
+        let mut sentences = std::mem::take(&mut self.pmtk_replies);
+        if !self.standby {
+            sentences.extend(self.nmea_epoch(fix).into_iter().map(|s| s + "\r\n"));
+        }
+        if let Some(uart) = &self.uart {
+            // what does not fit in the UART between two fixes never leaves the receiver
+            let mut budget = self.uart_baud as u64 / 10 * self.fix_interval_ms / 1000;
+            for sentence in &sentences {
+                let Some(left) = budget.checked_sub(sentence.len() as u64) else {
+                    break;
+                };
+                budget = left;
+                let _ = uart.send(UartMessage::Data(sentence.clone()));
+            }
+        }
+        let report = (report && self.urc_enabled && !self.standby)
+            .then(|| at!(self.navigation_info("+UGNSINF")));
+        if !self.nmea_output {
+            return report.into_iter().collect();
+        }
+        let last = sentences
+            .iter()
+            .rposition(|s| nmea::sentence_type(s) == self.seq)
+            .unwrap_or(sentences.len().saturating_sub(1));
+        let mut output = vec![];
+        for (i, sentence) in sentences.into_iter().enumerate() {
+            output.push(sentence);
+            if i == last {
+                output.extend(report.clone());
+            }
+        }
+        if output.is_empty() {
+            output.extend(report);
+        }
+        output
+    }
+
+    /// Opens the GNSS UART of `gnss.port`, it shares this model with the AT port. Returns a line
+    /// for the log when a port is configured
+    pub fn open_uart(gnss: &Arc<Mutex<GnssConfiguration>>, config: &Config) -> Option<String> {
+        let spec = config.get("gnss.port")?;
+        let baud = gnss.lock().unwrap().uart_baud;
+        match uart::open(spec, baud, gnss.clone()) {
+            Ok((uart, name)) => {
+                gnss.lock().unwrap().uart = Some(uart);
+                Some(format!("GNSS UART on {}", name))
+            }
+            Err(e) => Some(format!("Failed to open the GNSS UART on {}: {}", spec, e)),
+        }
+    }
+
+    pub fn set_uart_baud(&mut self, baud: u32) {
+        self.uart_baud = baud;
+        if let Some(uart) = &self.uart {
+            let _ = uart.send(UartMessage::Baud(baud));
+        }
+    }
+
+    /// The 21 comma separated fields of `+CGNSINF`/`+UGNSINF`, after the given prefix
+    pub fn navigation_info(&self, prefix: &str) -> String {
+        if !self.power {
+            return format!("{}: 0{}", prefix, ",".repeat(20));
+        }
+        let now = DateTime::from_unix(time::now());
+        let utc = format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}.{:03}",
+            now.year, now.month, now.day, now.hour, now.minute, now.second, now.millis
+        );
+        let signal = self.signal();
+        if !signal.fix {
+            return format!(
+                "{}: 1,0,{},,,,0.00,0.0,0,,,,,,{},0,0,,{},,",
+                prefix,
+                utc,
+                signal.in_view(),
+                signal.cn0_max()
+            );
+        }
+        let position = self.position();
+        format!(
+            "{}: 1,1,{},{:.6},{:.6},{:.3},{:.2},{:.1},1,,{:.1},{:.1},{:.1},,{},{},{},,{},,",
+            prefix,
+            utc,
+            position.latitude,
+            position.longitude,
+            position.altitude,
+            position.speed,
+            position.course,
+            signal.hdop,
+            signal.pdop,
+            signal.vdop,
+            signal.in_view(),
+            signal.used(),
+            signal.used_of(System::Glonass),
+            signal.cn0_max()
+        )
+    }
+}
+
+impl Sim868 {
+    pub fn gnss_command(&mut self, line: &str) -> Option<Vec<String>> {
+        let answer = if line.starts_with(AT_CGNSPWR) {
+            self.cgnspwr(line)
+        } else if line.starts_with(AT_CGNSINF) {
+            let info = self.gnss.lock().unwrap().navigation_info("+CGNSINF");
+            vec![at!(info), at!(OK)]
+        } else if line.starts_with(AT_CGNSURC) {
+            self.cgnsurc(line)
+        } else if line.starts_with(AT_CGNSSEQ) {
+            self.cgnsseq(line)
+        } else if line.starts_with(AT_CGNSTST) {
+            self.cgnstst(line)
+        } else if line.starts_with(AT_CGNSRST) {
+            self.cgnsrst(line)
+        } else if line.starts_with(AT_CGNSIPR) {
+            self.cgnsipr(line)
+        } else if line.starts_with(AT_CGNSCMD) {
+            self.cgnscmd(line)
+        } else if line.starts_with(AT_CGNSSAV) {
+            self.cgnssav(line)
+        } else if line.starts_with(AT_CGNSCHK) {
+            self.cgnschk(line)
+        } else if line.starts_with(AT_CGNSAID) {
+            self.cgnsaid(line)
+        } else {
+            return None;
+        };
+        Some(answer)
+    }
+
+    fn cgnspwr(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        if line[AT_CGNSPWR.len()..].starts_with('?') {
+            return vec![at!(format!("+CGNSPWR: {}", gnss.power as u8)), at!(OK)];
+        }
+        match params(line, AT_CGNSPWR).first().map(|p| p.as_str()) {
+            Some("0") => gnss.set_power(false),
+            Some("1") => gnss.set_power(true),
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+CGNSURC=<n>` reports `+UGNSINF` every n fixes, 0 turns it off
+    fn cgnsurc(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        if line[AT_CGNSURC.len()..].starts_with('?') {
+            let n = if gnss.urc_enabled { gnss.urc } else { 0 };
+            return vec![at!(format!("+CGNSURC: {}", n)), at!(OK)];
+        }
+        let Some(n) = params(line, AT_CGNSURC)
+            .first()
+            .and_then(|p| p.parse::<u8>().ok())
+        else {
+            return vec![at!(ERROR)];
+        };
+        gnss.urc_enabled = n > 0;
+        if n > 0 {
+            gnss.urc = n;
+        }
+        gnss.notify(GnssConfig::Urc(n));
+        vec![at!(OK)]
+    }
+
+    fn cgnsseq(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        if line[AT_CGNSSEQ.len()..].starts_with('?') {
+            return vec![at!(format!("+CGNSSEQ: \"{}\"", gnss.seq)), at!(OK)];
+        }
+        match params(line, AT_CGNSSEQ).first() {
+            Some(seq) if NMEA_SEQUENCES.contains(&seq.to_uppercase().as_str()) => {
+                gnss.seq = seq.to_uppercase();
+                vec![at!(OK)]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    /// `AT+CGNSTST=1` sends the NMEA stream to the port, `0` stops it
+    fn cgnstst(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        if line[AT_CGNSTST.len()..].starts_with('?') {
+            return vec![
+                at!(format!("+CGNSTST: {}", gnss.nmea_output as u8)),
+                at!(OK),
+            ];
+        }
+        match params(line, AT_CGNSTST).first().map(|p| p.as_str()) {
+            Some("0") => gnss.nmea_output = false,
+            Some("1") => gnss.nmea_output = true,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+CGNSRST=0` cold, `1` hot and `2` warm restart of a powered engine
+    fn cgnsrst(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        let mode = match params(line, AT_CGNSRST).first().map(|p| p.as_str()) {
+            Some("0") => StartMode::Cold,
+            Some("1") => StartMode::Hot,
+            Some("2") => StartMode::Warm,
+            _ => return vec![at!(ERROR)],
+        };
+        if !gnss.power {
+            return vec![at!(ERROR)];
+        }
+        gnss.acquisition.start(mode);
+        vec![at!(OK)]
+    }
+
+    /// Baud rate of the GNSS UART, `0` goes back to 115200 like autobauding would
+    fn cgnsipr(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        let rest = &line[AT_CGNSIPR.len()..];
+        if rest.starts_with("=?") {
+            let rates: Vec<String> = pmtk::GNSS_BAUD_RATES
+                .iter()
+                .map(|b| b.to_string())
+                .collect();
+            return vec![at!(format!("+CGNSIPR: (0,{})", rates.join(","))), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![at!(format!("+CGNSIPR: {}", gnss.uart_baud)), at!(OK)];
+        }
+        match params(line, AT_CGNSIPR)
+            .first()
+            .and_then(|p| p.parse::<u32>().ok())
+        {
+            Some(0) => gnss.set_uart_baud(115200),
+            Some(baud) if pmtk::GNSS_BAUD_RATES.contains(&baud) => gnss.set_uart_baud(baud),
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+CGNSCMD=0,"$PMTK..."` passes the command to the GNSS engine, its answer comes with the next fix
+    fn cgnscmd(&mut self, line: &str) -> Vec<String> {
+        let mut gnss = self.gnss.lock().unwrap();
+        let args = params(line, AT_CGNSCMD);
+        let (Some("0"), Some(command)) = (args.first().map(|a| a.as_str()), args.get(1)) else {
+            return vec![at!(ERROR)];
+        };
+        if !gnss.power {
+            return vec![at!(ERROR)];
+        }
+        let replies = gnss.pmtk(command);
+        gnss.pmtk_replies.extend(replies);
+        vec![at!(OK)]
+    }
+
+    /// `AT+CGNSSAV=3,3` keeps the body of the last HTTP download as the EPO file
+    fn cgnssav(&mut self, line: &str) -> Vec<String> {
+        if params(line, AT_CGNSSAV).first().map(|t| t.as_str()) != Some(EPO_FILE) {
+            return vec![at!(ERROR)];
+        }
+        let response = self.http.response.lock().unwrap();
+        let mut gnss = self.gnss.lock().unwrap();
+        let validity = gnss.epo_validity_h;
+        match response
+            .as_ref()
+            .and_then(|r| Epo::parse(&r.body, time::now(), validity))
+        {
+            Some(epo) => {
+                gnss.epo = Some(epo);
+                vec![at!(OK)]
+            }
+            None => vec![at!(ERROR)],
+        }
+    }
+
+    /// `AT+CGNSCHK=3,1` tells whether the saved EPO file covers the current time
+    fn cgnschk(&mut self, line: &str) -> Vec<String> {
+        if params(line, AT_CGNSCHK).first().map(|t| t.as_str()) != Some(EPO_FILE) {
+            return vec![at!(ERROR)];
+        }
+        let gnss = self.gnss.lock().unwrap();
+        let valid = gnss.epo.is_some_and(|epo| epo.is_valid(time::now()));
+        vec![at!(format!("+CGNSCHK: 3,{}", valid as u8)), at!(OK)]
+    }
+
+    /// `AT+CGNSAID=31,1,1` hands the saved EPO file to the powered engine
+    fn cgnsaid(&mut self, line: &str) -> Vec<String> {
+        if params(line, AT_CGNSAID).first().map(|m| m.as_str()) != Some(EPO_AID) {
+            return vec![at!(ERROR)];
+        }
+        let mut gnss = self.gnss.lock().unwrap();
+        match gnss.epo {
+            Some(epo) if gnss.power && epo.is_valid(time::now()) => {
+                gnss.acquisition.assist(epo.until);
+                vec![at!(OK)]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+}
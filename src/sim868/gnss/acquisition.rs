@@ -0,0 +1,209 @@
+use crate::{
+    config::Config,
+    sim868::gnss::constellation::{self, Satellite, System},
+    utils::time,
+};
+
+/// How long ephemeris from the last fix keeps a power-up a hot start
+const EPHEMERIS_VALIDITY: f64 = 4.0 * 3600.0;
+/// Seconds after a fix over which the DOPs settle to their final values
+const DOP_SETTLING: f64 = 30.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StartMode {
+    Cold,
+    Warm,
+    Hot,
+}
+
+/// Sky state of the receiver at a moment
+pub struct Signal {
+    pub fix: bool,
+    /// satellites tracked right now, fewer and weaker while searching
+    pub satellites: Vec<Satellite>,
+    pub hdop: f64,
+    pub pdop: f64,
+    pub vdop: f64,
+}
+
+impl Signal {
+    pub fn in_view(&self) -> usize {
+        self.satellites.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.satellites.iter().filter(|s| s.used).count()
+    }
+
+    pub fn used_of(&self, system: System) -> usize {
+        self.satellites
+            .iter()
+            .filter(|s| s.used && s.system == system)
+            .count()
+    }
+
+    /// strongest C/N0 in dB-Hz
+    pub fn cn0_max(&self) -> u8 {
+        self.satellites.iter().map(|s| s.snr).max().unwrap_or(0)
+    }
+}
+
+/// Time to first fix after a power-up or `AT+CGNSRST`, and the tunnels that drop the fix afterwards
+pub struct Acquisition {
+    pub ttff_cold: f64,
+    pub ttff_warm: f64,
+    pub ttff_hot: f64,
+    /// cold and warm TTFF while EPO assistance is loaded and valid
+    pub ttff_assisted: f64,
+    /// end of the validity of the EPO data loaded with AT+CGNSAID
+    pub assisted_until: Option<f64>,
+    /// `(start, end)` seconds after the start of the run without sky view
+    pub tunnels: Vec<(f64, f64)>,
+    /// when the current run started and the TTFF it needs
+    started: f64,
+    ttff: f64,
+    /// last time a fix was held, a hot start needs it to be recent
+    last_fix: Option<f64>,
+}
+
+impl Acquisition {
+    pub fn new() -> Acquisition {
+        Acquisition {
+            ttff_cold: 32.0,
+            ttff_warm: 28.0,
+            ttff_hot: 2.0,
+            ttff_assisted: 8.0,
+            assisted_until: None,
+            tunnels: vec![],
+            started: time::now(),
+            ttff: 32.0,
+            last_fix: None,
+        }
+    }
+
+    /// `gnss.ttff_cold_s`, `gnss.ttff_warm_s`, `gnss.ttff_hot_s` and `gnss.tunnels` as `start-end` pairs
+    pub fn from_config(config: &Config) -> Acquisition {
+        let mut acquisition = Acquisition::new();
+        acquisition.ttff_cold = config.get_or("gnss.ttff_cold_s", acquisition.ttff_cold);
+        acquisition.ttff_warm = config.get_or("gnss.ttff_warm_s", acquisition.ttff_warm);
+        acquisition.ttff_hot = config.get_or("gnss.ttff_hot_s", acquisition.ttff_hot);
+        acquisition.ttff_assisted =
+            config.get_or("gnss.ttff_assisted_s", acquisition.ttff_assisted);
+        acquisition.tunnels = config
+            .get("gnss.tunnels")
+            .unwrap_or("")
+            .split_whitespace()
+            .filter_map(|t| {
+                let (start, end) = t.split_once('-')?;
+                Some((start.parse().ok()?, end.parse().ok()?))
+            })
+            .collect();
+        acquisition
+    }
+
+    /// The start a power-up gets: hot while the last fix is recent, warm after that, cold without one
+    pub fn power_up_mode(&self) -> StartMode {
+        match self.last_fix {
+            Some(t) if time::now() - t < EPHEMERIS_VALIDITY => StartMode::Hot,
+            Some(_) => StartMode::Warm,
+            None => StartMode::Cold,
+        }
+    }
+
+    pub fn start(&mut self, mode: StartMode) {
+        self.started = time::now();
+        self.ttff = match mode {
+            StartMode::Cold => self.ttff_cold,
+            StartMode::Warm => self.ttff_warm,
+            StartMode::Hot => self.ttff_hot,
+        };
+        if self.is_assisted() {
+            self.ttff = self.ttff.min(self.ttff_assisted);
+        }
+        if mode == StartMode::Cold {
+            self.last_fix = None;
+        }
+    }
+
+    fn is_assisted(&self) -> bool {
+        self.assisted_until.is_some_and(|until| time::now() < until)
+    }
+
+    /// Loads EPO data valid until `until`, a search already running gets faster too
+    pub fn assist(&mut self, until: f64) {
+        self.assisted_until = Some(until);
+        if self.is_assisted() {
+            self.ttff = self.ttff.min(self.ttff_assisted);
+        }
+    }
+
+    /// Remembers the fix held at power-down for the next start
+    pub fn stop(&mut self, fixed: bool) {
+        if fixed {
+            self.last_fix = Some(time::now());
+        }
+    }
+
+    /// Seconds the current fix has been held, `None` without a fix
+    pub fn fix_age(&self) -> Option<f64> {
+        let elapsed = time::now() - self.started;
+        let mut age = elapsed - self.ttff;
+        if age < 0.0 {
+            return None;
+        }
+        for &(start, end) in &self.tunnels {
+            let reacquired = end + self.ttff_hot;
+            if (start..reacquired).contains(&elapsed) {
+                return None;
+            }
+            if elapsed >= reacquired && start < elapsed {
+                age = age.min(elapsed - reacquired);
+            }
+        }
+        Some(age)
+    }
+
+    /// Whether the run is inside one of the scripted tunnels
+    fn in_tunnel(&self) -> bool {
+        let elapsed = time::now() - self.started;
+        self.tunnels
+            .iter()
+            .any(|&(start, end)| (start..end).contains(&elapsed))
+    }
+
+    /// Satellites appear while searching and the DOPs start high and settle once the fix is there.
+    /// A sky with fewer than four usable satellites never gives a fix.
+    pub fn signal(&self, sky: &[Satellite]) -> Signal {
+        if let (Some(age), Some([hdop, pdop, vdop])) = (self.fix_age(), constellation::dops(sky)) {
+            let factor = 1.0 + 2.0 * (1.0 - age / DOP_SETTLING).max(0.0);
+            return Signal {
+                fix: true,
+                satellites: sky.to_vec(),
+                hdop: hdop * factor,
+                pdop: pdop * factor,
+                vdop: vdop * factor,
+            };
+        }
+        let progress = if self.in_tunnel() {
+            0.25
+        } else if self.ttff > 0.0 {
+            ((time::now() - self.started) / self.ttff).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let mut satellites = sky.to_vec();
+        satellites.sort_by_key(|s| std::cmp::Reverse(s.snr));
+        satellites.truncate((sky.len() as f64 * progress).round() as usize);
+        for satellite in satellites.iter_mut() {
+            satellite.snr = (satellite.snr as f64 * (0.6 + 0.4 * progress)) as u8;
+            satellite.used = false;
+        }
+        Signal {
+            fix: false,
+            satellites,
+            hdop: 99.9,
+            pdop: 99.9,
+            vdop: 99.9,
+        }
+    }
+}
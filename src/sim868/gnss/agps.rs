@@ -0,0 +1,39 @@
+/// Size of one six hour EPO segment: 32 satellite records of 72 bytes
+const EPO_SEGMENT: usize = 32 * 72;
+const EPO_SEGMENT_HOURS: f64 = 6.0;
+/// Unix time of the GPS epoch, 1980-01-06
+const GPS_EPOCH: f64 = 315_964_800.0;
+
+/// An EPO file saved in the module, the orbits it predicts hold between `from` and `until`
+#[derive(Clone, Copy)]
+pub struct Epo {
+    pub from: f64,
+    pub until: f64,
+}
+
+impl Epo {
+    /// MTK EPO files start every segment with its GPS hour in three little endian bytes.
+    /// Anything else is taken as assistance data good for `fallback_hours` from `now`.
+    pub fn parse(data: &[u8], now: f64, fallback_hours: f64) -> Option<Epo> {
+        if data.is_empty() {
+            return None;
+        }
+        if data.len().is_multiple_of(EPO_SEGMENT) {
+            let hour = u32::from_le_bytes([data[0], data[1], data[2], 0]) as f64;
+            let from = GPS_EPOCH + hour * 3600.0;
+            let segments = (data.len() / EPO_SEGMENT) as f64;
+            return Some(Epo {
+                from,
+                until: from + segments * EPO_SEGMENT_HOURS * 3600.0,
+            });
+        }
+        Some(Epo {
+            from: now,
+            until: now + fallback_hours * 3600.0,
+        })
+    }
+
+    pub fn is_valid(&self, now: f64) -> bool {
+        (self.from..self.until).contains(&now)
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs;
+
+/// Weakest signal the receiver still uses in its solution, in dB-Hz
+const USED_SNR: u8 = 25;
+/// Satellites below this elevation are tracked but left out of the solution
+const ELEVATION_MASK: u8 = 5;
+
+/// GPS, GLONASS, BeiDou and Galileo satellites in view of the emulated antenna on a clear day
+const DEFAULT_SKY: [(System, u8, u8, u16, u8); 16] = [
+    (System::Gps, 2, 62, 38, 45),
+    (System::Gps, 5, 41, 112, 43),
+    (System::Gps, 12, 28, 205, 40),
+    (System::Gps, 13, 75, 290, 44),
+    (System::Gps, 15, 18, 320, 36),
+    (System::Gps, 19, 33, 160, 41),
+    (System::Gps, 24, 8, 75, 27),
+    (System::Gps, 29, 52, 250, 42),
+    (System::Glonass, 65, 47, 60, 40),
+    (System::Glonass, 71, 22, 180, 35),
+    (System::Glonass, 72, 66, 300, 42),
+    (System::Glonass, 80, 4, 130, 22),
+    (System::Beidou, 7, 36, 95, 34),
+    (System::Beidou, 10, 12, 225, 28),
+    (System::Galileo, 11, 55, 140, 39),
+    (System::Galileo, 36, 26, 345, 33),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum System {
+    Gps,
+    Glonass,
+    Beidou,
+    Galileo,
+}
+
+impl System {
+    pub const ALL: [System; 4] = [
+        System::Gps,
+        System::Glonass,
+        System::Beidou,
+        System::Galileo,
+    ];
+
+    /// NMEA talker of the GSA and GSV sentences of the system
+    pub fn talker(self) -> &'static str {
+        match self {
+            System::Gps => "GP",
+            System::Glonass => "GL",
+            System::Beidou => "GB",
+            System::Galileo => "GA",
+        }
+    }
+
+    fn parse(name: &str) -> Option<System> {
+        match name.to_uppercase().as_str() {
+            "GPS" | "GP" => Some(System::Gps),
+            "GLONASS" | "GL" => Some(System::Glonass),
+            "BEIDOU" | "BDS" | "GB" | "BD" => Some(System::Beidou),
+            "GALILEO" | "GA" => Some(System::Galileo),
+            _ => None,
+        }
+    }
+}
+
+/// A virtual satellite, elevation and azimuth in degrees and SNR as C/N0 in dB-Hz
+#[derive(Clone)]
+pub struct Satellite {
+    pub system: System,
+    pub prn: u8,
+    pub elevation: u8,
+    pub azimuth: u16,
+    /// 0 when the satellite is in view but not tracked
+    pub snr: u8,
+    pub used: bool,
+}
+
+impl Satellite {
+    fn new(system: System, prn: u8, elevation: u8, azimuth: u16, snr: u8) -> Satellite {
+        Satellite {
+            system,
+            prn,
+            elevation,
+            azimuth,
+            snr,
+            used: false,
+        }
+        .select()
+    }
+
+    /// Whether the receiver would put the satellite in its solution
+    fn select(mut self) -> Satellite {
+        self.used = self.snr >= USED_SNR && self.elevation >= ELEVATION_MASK;
+        self
+    }
+}
+
+pub fn default_sky() -> Vec<Satellite> {
+    DEFAULT_SKY
+        .iter()
+        .map(|&(system, prn, elevation, azimuth, snr)| {
+            Satellite::new(system, prn, elevation, azimuth, snr)
+        })
+        .collect()
+}
+
+/// A sky profile has one `<system> <prn> <elevation> <azimuth> <snr>` satellite per line,
+/// system being GPS, GLONASS, BEIDOU or GALILEO, `#` starts a comment
+pub fn load_sky(path: &str) -> Option<Vec<Satellite>> {
+    let content = fs::read_to_string(path).ok()?;
+    let sky = content
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let fields: Vec<&str> = l
+                .split([' ', '\t', ','])
+                .filter(|f| !f.is_empty())
+                .collect();
+            Some(Satellite::new(
+                System::parse(fields.first()?)?,
+                fields.get(1)?.parse().ok()?,
+                fields.get(2)?.parse().ok()?,
+                fields.get(3)?.parse().ok()?,
+                fields.get(4)?.parse().ok()?,
+            ))
+        })
+        .collect();
+    Some(sky)
+}
+
+/// HDOP, PDOP and VDOP of the geometry of the used satellites, `None` with fewer than four
+pub fn dops(satellites: &[Satellite]) -> Option<[f64; 3]> {
+    let rows: Vec<[f64; 4]> = satellites
+        .iter()
+        .filter(|s| s.used)
+        .map(|s| {
+            let (elevation, azimuth) = (
+                (s.elevation as f64).to_radians(),
+                (s.azimuth as f64).to_radians(),
+            );
+            [
+                elevation.cos() * azimuth.sin(),
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                1.0,
+            ]
+        })
+        .collect();
+    if rows.len() < 4 {
+        return None;
+    }
+    // covariance of the least squares solution, the inverse of GᵀG
+    let mut normal = [[0.0; 4]; 4];
+    for row in &rows {
+        for i in 0..4 {
+            for j in 0..4 {
+                normal[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    let q = invert(normal)?;
+    Some([
+        (q[0][0] + q[1][1]).sqrt(),
+        (q[0][0] + q[1][1] + q[2][2]).sqrt(),
+        q[2][2].sqrt(),
+    ])
+}
+
+/// Gauss-Jordan inverse, `None` for a singular geometry
+fn invert(mut m: [[f64; 4]; 4]) -> Option<[[f64; 4]; 4]> {
+    let mut inverse = [[0.0; 4]; 4];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        m.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let scale = m[col][col];
+        for j in 0..4 {
+            m[col][j] /= scale;
+            inverse[col][j] /= scale;
+        }
+        for row in 0..4 {
+            if row != col {
+                let factor = m[row][col];
+                for j in 0..4 {
+                    m[row][j] -= factor * m[col][j];
+                    inverse[row][j] -= factor * inverse[col][j];
+                }
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn used(elevation: u8, azimuth: u16) -> Satellite {
+        Satellite {
+            system: System::Gps,
+            prn: 1,
+            elevation,
+            azimuth,
+            snr: 40,
+            used: true,
+        }
+    }
+
+    #[test]
+    fn dops_of_a_zenith_and_three_horizon_satellites() {
+        let sky = [used(90, 0), used(0, 0), used(0, 120), used(0, 240)];
+        let [hdop, pdop, vdop] = dops(&sky).unwrap();
+        // GᵀG is diagonal in x and y (3/2) and couples z with the clock, [[1, 1], [1, 4]]
+        assert!((hdop - (4.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert!((vdop - (4.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert!((pdop - (8.0f64 / 3.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dops_need_four_used_satellites() {
+        let mut sky = vec![used(90, 0), used(30, 0), used(30, 120), used(30, 240)];
+        sky[0].used = false;
+        assert!(dops(&sky).is_none());
+        sky[0].used = true;
+        assert!(dops(&sky).is_some());
+    }
+
+    #[test]
+    fn dops_of_a_singular_geometry() {
+        let sky = [used(45, 90), used(45, 90), used(45, 90), used(45, 90)];
+        assert!(dops(&sky).is_none());
+    }
+
+    #[test]
+    fn weak_and_low_satellites_are_not_used() {
+        assert!(Satellite::new(System::Gps, 1, 40, 0, 40).used);
+        assert!(!Satellite::new(System::Gps, 1, 40, 0, USED_SNR - 1).used);
+        assert!(!Satellite::new(System::Gps, 1, ELEVATION_MASK - 1, 0, 40).used);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{fmt, fs};
+
+use crate::{
+    sim868::gnss::TrackSample,
+    utils::{geo, time},
+};
+
+/// A change forced on the GNSS output from the UI or a scenario script
+#[derive(Clone, Copy)]
+pub enum Injection {
+    /// move to the coordinates and stay there, a playing track stops
+    Teleport {
+        latitude: f64,
+        longitude: f64,
+    },
+    /// report a position `meters` away for `seconds`, like a multipath glitch
+    Jump {
+        meters: f64,
+        bearing: f64,
+        seconds: f64,
+    },
+    /// keep reporting the current position
+    Freeze,
+    Unfreeze,
+    /// report the speed for `seconds`, or until cleared when 0
+    Speed {
+        kmh: f64,
+        seconds: f64,
+    },
+    /// drop every injection still in effect
+    Clear,
+}
+
+impl Injection {
+    /// `teleport <lat> <lon>`, `jump <meters> [<bearing>] [<seconds>]`, `freeze`, `unfreeze`,
+    /// `speed <km/h> [<seconds>]` or `clear`
+    pub fn parse(text: &str) -> Option<Injection> {
+        let mut words = text.split_whitespace();
+        let command = words.next()?.to_lowercase();
+        let numbers: Vec<f64> = words.map(|w| w.parse().ok()).collect::<Option<_>>()?;
+        let number = |i: usize, default: Option<f64>| numbers.get(i).copied().or(default);
+        Some(match command.as_str() {
+            "teleport" => Injection::Teleport {
+                latitude: number(0, None)?,
+                longitude: number(1, None)?,
+            },
+            "jump" => Injection::Jump {
+                meters: number(0, None)?,
+                bearing: number(1, Some(90.0))?,
+                seconds: number(2, Some(5.0))?,
+            },
+            "freeze" => Injection::Freeze,
+            "unfreeze" => Injection::Unfreeze,
+            "speed" => Injection::Speed {
+                kmh: number(0, None)?,
+                seconds: number(1, Some(0.0))?,
+            },
+            "clear" => Injection::Clear,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Injection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Injection::Teleport {
+                latitude,
+                longitude,
+            } => write!(f, "teleport {:.6} {:.6}", latitude, longitude),
+            Injection::Jump {
+                meters,
+                bearing,
+                seconds,
+            } => write!(f, "jump {} {} {}", meters, bearing, seconds),
+            Injection::Freeze => write!(f, "freeze"),
+            Injection::Unfreeze => write!(f, "unfreeze"),
+            Injection::Speed { kmh, seconds } => write!(f, "speed {} {}", kmh, seconds),
+            Injection::Clear => write!(f, "clear"),
+        }
+    }
+}
+
+/// Injections in effect, applied over the true position
+#[derive(Default)]
+pub struct Deviation {
+    pub frozen: Option<TrackSample>,
+    /// meters, bearing and end of a glitch
+    pub jump: Option<(f64, f64, f64)>,
+    /// forced speed and its end, `None` for no end
+    pub speed: Option<(f64, Option<f64>)>,
+}
+
+impl Deviation {
+    pub fn apply(&self, position: TrackSample) -> TrackSample {
+        let now = time::now();
+        let mut position = self.frozen.unwrap_or(position);
+        if self.frozen.is_some() {
+            position.speed = 0.0;
+        }
+        if let Some((meters, bearing, until)) = self.jump {
+            if now < until {
+                (position.latitude, position.longitude) =
+                    geo::offset(position.latitude, position.longitude, meters, bearing);
+            }
+        }
+        if let Some((kmh, until)) = self.speed {
+            if until.is_none_or(|until| now < until) {
+                position.speed = kmh;
+            }
+        }
+        position
+    }
+
+    /// Drops the timed injections that are over
+    pub fn expire(&mut self) {
+        let now = time::now();
+        if self.jump.is_some_and(|(_, _, until)| now >= until) {
+            self.jump = None;
+        }
+        if self
+            .speed
+            .is_some_and(|(_, until)| until.is_some_and(|until| now >= until))
+        {
+            self.speed = None;
+        }
+    }
+}
+
+/// `gnss.scenario`: one `<seconds> <injection>` per line, timed from the start of the emulator
+pub struct Scenario {
+    steps: Vec<(f64, Injection)>,
+    next: usize,
+    started: f64,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Option<Scenario> {
+        let content = fs::read_to_string(path).ok()?;
+        let mut steps: Vec<(f64, Injection)> = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let (at, injection) = l.split_once(char::is_whitespace)?;
+                Some((at.parse().ok()?, Injection::parse(injection)?))
+            })
+            .collect();
+        steps.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Scenario {
+            steps,
+            next: 0,
+            started: time::now(),
+        })
+    }
+
+    /// The injections whose time has come since the last call
+    pub fn due(&mut self) -> Vec<Injection> {
+        let elapsed = time::now() - self.started;
+        let mut due = vec![];
+        while let Some((at, injection)) = self.steps.get(self.next) {
+            if *at > elapsed {
+                break;
+            }
+            due.push(*injection);
+            self.next += 1;
+        }
+        due
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs;
+
+use crate::{
+    sim868::gnss::{GnssConfiguration, Satellite, System},
+    utils::time::{self, DateTime},
+};
+
+const KNOTS_PER_KMH: f64 = 1.0 / 1.852;
+
+/// A recorded NMEA stream replayed verbatim, one epoch per second
+pub struct NmeaLog {
+    epochs: Vec<Vec<String>>,
+    next: usize,
+}
+
+impl NmeaLog {
+    /// Epochs are split where the first sentence type of the log comes round again
+    pub fn load(path: &str) -> Option<NmeaLog> {
+        let content = fs::read_to_string(path).ok()?;
+        let mut epochs: Vec<Vec<String>> = vec![];
+        let mut first = None;
+        for line in content.lines().map(|l| l.trim()) {
+            if !line.starts_with('$') {
+                continue;
+            }
+            let kind = sentence_type(line);
+            if first.is_none() {
+                first = Some(kind.to_owned());
+            }
+            if epochs.is_empty() || first.as_deref() == Some(kind) {
+                epochs.push(vec![]);
+            }
+            epochs.last_mut().unwrap().push(line.to_owned());
+        }
+        if epochs.is_empty() {
+            return None;
+        }
+        Some(NmeaLog { epochs, next: 0 })
+    }
+
+    /// The next epoch of the log, wrapping around at its end
+    pub fn next_epoch(&mut self) -> Vec<String> {
+        let epoch = self.epochs[self.next].clone();
+        self.next = (self.next + 1) % self.epochs.len();
+        epoch
+    }
+}
+
+/// `GGA` for `$GNGGA,...`
+pub fn sentence_type(sentence: &str) -> &str {
+    sentence
+        .get(3..)
+        .and_then(|s| s.split([',', '*']).next())
+        .unwrap_or("")
+}
+
+/// XOR of the characters between `$` and `*`
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0u8, |sum, b| sum ^ b)
+}
+
+/// Wraps the sentence body between `$` and `*` and appends its checksum
+pub fn sentence(body: String) -> String {
+    format!("${}*{:02X}", body, checksum(&body))
+}
+
+/// `ddmm.mmmmmm` (`dddmm.mmmmmm` for longitudes) and the hemisphere letter
+fn coordinate(value: f64, degree_digits: usize, positive: char, negative: char) -> String {
+    let abs = value.abs();
+    let mut degrees = abs.trunc() as u32;
+    let mut minutes = ((abs - abs.trunc()) * 60.0 * 1e6).round() / 1e6;
+    if minutes >= 60.0 {
+        degrees += 1;
+        minutes -= 60.0;
+    }
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    format!(
+        "{:0width$}{:09.6},{}",
+        degrees,
+        minutes,
+        hemisphere,
+        width = degree_digits
+    )
+}
+
+impl GnssConfiguration {
+    /// The sentences of the `fix`-th fix: GGA, GSA, GSV, RMC, VTG and ZDA as far as the PMTK314
+    /// filter lets them through, or the next epoch of `gnss.nmea_log` when a recording is replayed
+    pub fn nmea_epoch(&mut self, fix: u64) -> Vec<String> {
+        if let Some(log) = self.nmea_log.as_mut() {
+            return log.next_epoch();
+        }
+        let now = DateTime::from_unix(time::now());
+        let utc = format!(
+            "{:02}{:02}{:02}.{:03}",
+            now.hour, now.minute, now.second, now.millis
+        );
+        let date = format!("{:02}{:02}{:02}", now.day, now.month, now.year % 100);
+        let signal = self.signal();
+        let fixed = signal.fix;
+        let position = self.position();
+        let satellites = &signal.satellites;
+        // every system of the sky profile reports, even before its satellites are tracked
+        let systems: Vec<System> = System::ALL
+            .into_iter()
+            .filter(|system| self.sky.iter().any(|s| s.system == *system))
+            .collect();
+        let mut sentences = vec![];
+
+        sentences.push(sentence(if fixed {
+            format!(
+                "GNGGA,{},{},{},1,{:02},{:.1},{:.1},M,0.0,M,,",
+                utc,
+                coordinate(position.latitude, 2, 'N', 'S'),
+                coordinate(position.longitude, 3, 'E', 'W'),
+                signal.used(),
+                signal.hdop,
+                position.altitude
+            )
+        } else {
+            format!("GNGGA,{},,,,,0,00,,,M,,M,,", utc)
+        }));
+
+        for system in &systems {
+            let talker = system.talker();
+            let mut used: Vec<String> = satellites
+                .iter()
+                .filter(|s| s.system == *system && s.used)
+                .map(|s| s.prn.to_string())
+                .take(12)
+                .collect();
+            used.resize(12, String::new());
+            sentences.push(sentence(if fixed {
+                format!(
+                    "{}GSA,A,3,{},{:.1},{:.1},{:.1}",
+                    talker,
+                    used.join(","),
+                    signal.pdop,
+                    signal.hdop,
+                    signal.vdop
+                )
+            } else {
+                format!("{}GSA,A,1,{},,,", talker, used.join(","))
+            }));
+        }
+
+        for system in &systems {
+            let talker = system.talker();
+            let in_view: Vec<&Satellite> =
+                satellites.iter().filter(|s| s.system == *system).collect();
+            let messages = in_view.len().div_ceil(4).max(1);
+            for (i, group) in in_view
+                .chunks(4)
+                .chain(in_view.is_empty().then_some(&[][..]))
+                .enumerate()
+            {
+                let mut body = format!("{}GSV,{},{},{:02}", talker, messages, i + 1, in_view.len());
+                for s in group {
+                    body += &format!(",{:02},{:02},{:03},", s.prn, s.elevation, s.azimuth);
+                    if s.snr > 0 {
+                        body += &format!("{:02}", s.snr);
+                    }
+                }
+                sentences.push(sentence(body));
+            }
+        }
+
+        let knots = position.speed * KNOTS_PER_KMH;
+        sentences.push(sentence(if fixed {
+            format!(
+                "GNRMC,{},A,{},{},{:.2},{:.2},{},,,A",
+                utc,
+                coordinate(position.latitude, 2, 'N', 'S'),
+                coordinate(position.longitude, 3, 'E', 'W'),
+                knots,
+                position.course,
+                date
+            )
+        } else {
+            format!("GNRMC,{},V,,,,,,,{},,,N", utc, date)
+        }));
+
+        sentences.push(sentence(if fixed {
+            format!(
+                "GNVTG,{:.2},T,,M,{:.2},N,{:.2},K,A",
+                position.course, knots, position.speed
+            )
+        } else {
+            "GNVTG,,T,,M,,N,,K,N".to_owned()
+        }));
+
+        sentences.push(sentence(format!(
+            "GNZDA,{},{:02},{:02},{:04},,",
+            utc, now.day, now.month, now.year
+        )));
+        sentences.retain(|s| self.sentence_due(sentence_type(s), fix));
+        sentences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_of_a_known_sentence() {
+        let body = "GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,";
+        assert_eq!(checksum(body), 0x47);
+        assert_eq!(sentence(body.to_owned()), format!("${}*47", body));
+    }
+
+    #[test]
+    fn checksum_is_two_upper_case_digits() {
+        assert_eq!(sentence("PMTK001,220,3".to_owned()), "$PMTK001,220,3*30");
+        assert_eq!(checksum(""), 0);
+        assert!(sentence("A".to_owned()).ends_with("*41"));
+        assert!(sentence("AB".to_owned()).ends_with("*03"));
+    }
+
+    #[test]
+    fn sentence_type_skips_the_talker() {
+        assert_eq!(sentence_type("$GNGGA,1,2*00"), "GGA");
+        assert_eq!(sentence_type("$GPRMC*00"), "RMC");
+        assert_eq!(sentence_type("$"), "");
+    }
+
+    #[test]
+    fn coordinates_in_degrees_and_minutes() {
+        assert_eq!(coordinate(35.5, 2, 'N', 'S'), "3530.000000,N");
+        assert_eq!(coordinate(-51.25, 3, 'E', 'W'), "05115.000000,W");
+        // minutes that round up to 60 carry into the degrees
+        assert_eq!(coordinate(1.999_999_999_9, 2, 'N', 'S'), "0200.000000,N");
+    }
+}
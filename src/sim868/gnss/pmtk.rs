@@ -0,0 +1,119 @@
+use crate::sim868::{
+    gnss::{nmea, GnssConfiguration, StartMode},
+    GnssConfig,
+};
+
+/// Sentences of the PMTK314 output filter, by field position
+const PMTK314_FIELDS: [(usize, &str); 7] = [
+    (0, "GLL"),
+    (1, "RMC"),
+    (2, "VTG"),
+    (3, "GGA"),
+    (4, "GSA"),
+    (5, "GSV"),
+    (17, "ZDA"),
+];
+
+/// Baud rates the GNSS UART runs at, shared by AT+CGNSIPR and PMTK251
+pub const GNSS_BAUD_RATES: [u32; 6] = [4800, 9600, 19200, 38400, 57600, 115200];
+
+const PMTK_INVALID: u8 = 0;
+const PMTK_UNSUPPORTED: u8 = 1;
+const PMTK_FAILED: u8 = 2;
+const PMTK_SUCCEEDED: u8 = 3;
+
+/// How often every sentence type is sent, in fixes, 0 for never
+pub fn default_sentence_rates() -> Vec<(String, u8)> {
+    PMTK314_FIELDS
+        .iter()
+        .map(|(_, kind)| (kind.to_string(), (*kind != "GLL") as u8))
+        .collect()
+}
+
+impl GnssConfiguration {
+    /// Whether the `fix`-th fix carries a sentence of the given type under the PMTK314 filter
+    pub fn sentence_due(&self, kind: &str, fix: u64) -> bool {
+        self.sentence_rates
+            .iter()
+            .find(|(k, _)| k == kind)
+            .is_none_or(|(_, rate)| *rate > 0 && fix.is_multiple_of(*rate as u64))
+    }
+
+    /// Runs a `$PMTK...*hh` command and gives back the sentences the receiver answers with.
+    /// Any command wakes the receiver from standby.
+    pub fn pmtk(&mut self, command: &str) -> Vec<String> {
+        self.standby = false;
+        let command = command.trim();
+        let (body, checksum) = match command.trim_start_matches('$').split_once('*') {
+            Some((body, checksum)) => (body, Some(checksum)),
+            None => (command.trim_start_matches('$'), None),
+        };
+        let fields: Vec<&str> = body.split(',').collect();
+        let Some(number) = fields[0].strip_prefix("PMTK") else {
+            return vec![];
+        };
+        let valid =
+            checksum.is_none_or(|c| u8::from_str_radix(c, 16).ok() == Some(nmea::checksum(body)));
+        let flag = if !valid {
+            PMTK_INVALID
+        } else {
+            match number {
+                "101" | "102" | "103" | "104" => {
+                    self.acquisition.start(match number {
+                        "101" => StartMode::Hot,
+                        "102" => StartMode::Warm,
+                        _ => StartMode::Cold,
+                    });
+                    // restarts answer with the start-up message instead of an acknowledgement
+                    return vec![nmea::sentence("PMTK010,001".to_owned()) + "\r\n"];
+                }
+                "161" => {
+                    self.standby = true;
+                    PMTK_SUCCEEDED
+                }
+                "220" => match fields.get(1).and_then(|f| f.parse::<u64>().ok()) {
+                    Some(interval @ 100..=10000) => {
+                        self.fix_interval_ms = interval;
+                        self.notify(GnssConfig::Rate(interval));
+                        PMTK_SUCCEEDED
+                    }
+                    _ => PMTK_FAILED,
+                },
+                "251" => match fields.get(1).and_then(|f| f.parse::<u32>().ok()) {
+                    Some(0) => {
+                        self.set_uart_baud(115200);
+                        PMTK_SUCCEEDED
+                    }
+                    Some(baud) if GNSS_BAUD_RATES.contains(&baud) => {
+                        self.set_uart_baud(baud);
+                        PMTK_SUCCEEDED
+                    }
+                    _ => PMTK_FAILED,
+                },
+                "314" => {
+                    if fields.get(1) == Some(&"-1") {
+                        self.sentence_rates = default_sentence_rates();
+                        PMTK_SUCCEEDED
+                    } else if fields.len() < 7 {
+                        PMTK_FAILED
+                    } else {
+                        for (position, kind) in PMTK314_FIELDS {
+                            let rate = fields
+                                .get(position + 1)
+                                .and_then(|f| f.parse::<u8>().ok())
+                                .unwrap_or(0);
+                            if let Some(entry) =
+                                self.sentence_rates.iter_mut().find(|(k, _)| k == kind)
+                            {
+                                entry.1 = rate.min(5);
+                            }
+                        }
+                        PMTK_SUCCEEDED
+                    }
+                }
+                _ => PMTK_UNSUPPORTED,
+            }
+        };
+        vec![nmea::sentence(format!("PMTK001,{},{}", number, flag)) + "\r\n"]
+    }
+}
@@ -0,0 +1,392 @@
+use std::{fs, path::Path};
+
+use crate::utils::{
+    geo,
+    time::{self, DateTime},
+};
+
+/// One recorded position, `time` counts seconds from the first point of the track
+#[derive(Clone, Copy)]
+pub struct TrackPoint {
+    pub time: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+/// Where playback is at a moment, speed in km/h and course in degrees
+#[derive(Clone, Copy)]
+pub struct TrackSample {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+    pub speed: f64,
+    pub course: f64,
+}
+
+/// A GPX, KML or CSV route replayed against the wall clock
+pub struct Track {
+    points: Vec<TrackPoint>,
+    pub rate: f64,
+    pub looped: bool,
+    /// track time reached at `anchor`, re-anchored whenever the rate changes
+    offset: f64,
+    anchor: f64,
+}
+
+/// Text between `<tag>` and `</tag>`, searching from the start of `block`
+fn tag_text<'a>(block: &'a str, tag: &str) -> Option<&'a str> {
+    let start = block.find(&format!("<{}", tag))?;
+    let start = start + block[start..].find('>')? + 1;
+    let end = start + block[start..].find(&format!("</{}>", tag))?;
+    Some(block[start..end].trim())
+}
+
+fn attribute(tag: &str, name: &str) -> Option<f64> {
+    let start = tag.find(&format!(" {}=", name))? + name.len() + 2;
+    let quote = tag[start..].chars().next()?;
+    let value = &tag[start + 1..];
+    value[..value.find(quote)?].trim().parse().ok()
+}
+
+/// Gives every point a time from the distance covered at `speed_kmh`, for tracks recorded without timestamps
+fn timestamp_by_distance(points: &mut [TrackPoint], speed_kmh: f64) {
+    let speed = (speed_kmh / 3.6).max(0.1);
+    for i in 1..points.len() {
+        let (a, b) = (points[i - 1], points[i]);
+        let distance = geo::distance_m(a.latitude, a.longitude, b.latitude, b.longitude);
+        points[i].time = a.time + distance / speed;
+    }
+}
+
+fn parse_gpx(content: &str) -> (Vec<TrackPoint>, bool) {
+    let mut points = vec![];
+    let mut timed = true;
+    let mut search = content;
+    while let Some(start) = search.find("<trkpt").or_else(|| search.find("<rtept")) {
+        let block = &search[start..];
+        let head_end = block.find('>').unwrap_or(block.len());
+        let head = &block[..head_end];
+        let end = if head.ends_with('/') {
+            head_end
+        } else {
+            block
+                .find("</trkpt>")
+                .or_else(|| block.find("</rtept>"))
+                .unwrap_or(head_end)
+        };
+        let body = &block[..end];
+        if let (Some(latitude), Some(longitude)) = (attribute(head, "lat"), attribute(head, "lon"))
+        {
+            let time = tag_text(body, "time").and_then(DateTime::parse_iso8601);
+            timed &= time.is_some();
+            points.push(TrackPoint {
+                time: time.map_or(0.0, |t| t.to_unix()),
+                latitude,
+                longitude,
+                altitude: tag_text(body, "ele")
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(0.0),
+            });
+        }
+        search = &block[end.max(1)..];
+    }
+    (points, timed)
+}
+
+fn parse_kml(content: &str) -> (Vec<TrackPoint>, bool) {
+    // a gx:Track carries timestamps, plain coordinates lists do not
+    let whens: Vec<f64> = content
+        .split("<when>")
+        .skip(1)
+        .filter_map(|w| DateTime::parse_iso8601(w.split('<').next()?))
+        .map(|t| t.to_unix())
+        .collect();
+    let coords: Vec<&str> = content
+        .split("<gx:coord>")
+        .skip(1)
+        .filter_map(|c| c.split('<').next())
+        .collect();
+    if !coords.is_empty() && coords.len() == whens.len() {
+        let points = coords
+            .iter()
+            .zip(whens)
+            .filter_map(|(c, time)| {
+                let mut values = c.split_whitespace().map(|v| v.parse::<f64>().ok());
+                Some(TrackPoint {
+                    time,
+                    longitude: values.next()??,
+                    latitude: values.next()??,
+                    altitude: values.next().flatten().unwrap_or(0.0),
+                })
+            })
+            .collect();
+        return (points, true);
+    }
+    let mut points = vec![];
+    for block in content.split("<coordinates>").skip(1) {
+        let block = block.split("</coordinates>").next().unwrap_or("");
+        for tuple in block.split_whitespace() {
+            let mut values = tuple.split(',').map(|v| v.trim().parse::<f64>().ok());
+            if let (Some(Some(longitude)), Some(Some(latitude))) = (values.next(), values.next()) {
+                points.push(TrackPoint {
+                    time: 0.0,
+                    latitude,
+                    longitude,
+                    altitude: values.next().flatten().unwrap_or(0.0),
+                });
+            }
+        }
+    }
+    (points, false)
+}
+
+/// `time,lat,lon,alt` columns in any order when the file has a header, `lat,lon[,alt]` otherwise.
+/// Times are ISO 8601 or plain seconds.
+fn parse_csv(content: &str) -> (Vec<TrackPoint>, bool) {
+    let mut lines = content
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .peekable();
+    let mut columns = [None, Some(0), Some(1), Some(2)];
+    if let Some(header) = lines.peek() {
+        let names: Vec<String> = header.split(',').map(|n| n.trim().to_lowercase()).collect();
+        if names.iter().any(|n| n.parse::<f64>().is_err()) {
+            let find =
+                |candidates: &[&str]| names.iter().position(|n| candidates.contains(&n.as_str()));
+            columns = [
+                find(&["time", "timestamp", "t"]),
+                find(&["lat", "latitude"]),
+                find(&["lon", "lng", "long", "longitude"]),
+                find(&["alt", "altitude", "ele", "elevation"]),
+            ];
+            lines.next();
+        }
+    }
+    let mut points = vec![];
+    let mut timed = columns[0].is_some();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let number =
+            |column: Option<usize>| column.and_then(|c| fields.get(c)?.parse::<f64>().ok());
+        let (Some(latitude), Some(longitude)) = (number(columns[1]), number(columns[2])) else {
+            continue;
+        };
+        let time = columns[0].and_then(|c| {
+            let field = fields.get(c)?;
+            field
+                .parse::<f64>()
+                .ok()
+                .or_else(|| DateTime::parse_iso8601(field).map(|t| t.to_unix()))
+        });
+        timed &= time.is_some();
+        points.push(TrackPoint {
+            time: time.unwrap_or(0.0),
+            latitude,
+            longitude,
+            altitude: number(columns[3]).unwrap_or(0.0),
+        });
+    }
+    (points, timed)
+}
+
+impl Track {
+    /// Picks the parser from the file extension, `speed_kmh` paces tracks without timestamps
+    pub fn load(path: &str, speed_kmh: f64) -> Option<Track> {
+        let content = fs::read_to_string(path).ok()?;
+        let extension = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        let (mut points, timed) = match extension.as_deref() {
+            Some("gpx") => parse_gpx(&content),
+            Some("kml") => parse_kml(&content),
+            _ => parse_csv(&content),
+        };
+        if points.is_empty() {
+            return None;
+        }
+        if timed {
+            let start = points[0].time;
+            points.iter_mut().for_each(|p| p.time -= start);
+        } else {
+            points[0].time = 0.0;
+            timestamp_by_distance(&mut points, speed_kmh);
+        }
+        Some(Track {
+            points,
+            rate: 1.0,
+            looped: true,
+            offset: 0.0,
+            anchor: time::now(),
+        })
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.points.last().map_or(0.0, |p| p.time)
+    }
+
+    /// Seconds into the track playback has reached
+    pub fn elapsed(&self) -> f64 {
+        let elapsed = self.offset + (time::now() - self.anchor) * self.rate;
+        if self.looped && self.duration() > 0.0 {
+            elapsed % self.duration()
+        } else {
+            elapsed.min(self.duration())
+        }
+    }
+
+    /// Changes the playback rate without making the position jump
+    pub fn set_rate(&mut self, rate: f64) {
+        self.offset = self.elapsed();
+        self.anchor = time::now();
+        self.rate = rate.max(0.0);
+    }
+
+    pub fn sample(&self) -> TrackSample {
+        let elapsed = self.elapsed();
+        let next = self
+            .points
+            .iter()
+            .position(|p| p.time > elapsed)
+            .unwrap_or(self.points.len());
+        if next == 0 || next == self.points.len() {
+            let p = if next == 0 {
+                self.points[0]
+            } else {
+                self.points[next - 1]
+            };
+            return TrackSample {
+                latitude: p.latitude,
+                longitude: p.longitude,
+                altitude: p.altitude,
+                speed: 0.0,
+                course: 0.0,
+            };
+        }
+        let (a, b) = (self.points[next - 1], self.points[next]);
+        let span = b.time - a.time;
+        let f = (elapsed - a.time) / span;
+        let distance = geo::distance_m(a.latitude, a.longitude, b.latitude, b.longitude);
+        TrackSample {
+            latitude: a.latitude + (b.latitude - a.latitude) * f,
+            longitude: a.longitude + (b.longitude - a.longitude) * f,
+            altitude: a.altitude + (b.altitude - a.altitude) * f,
+            // playback faster than real time is reported as a faster vehicle, so positions stay consistent
+            speed: distance / span * 3.6 * self.rate,
+            course: geo::bearing_deg(a.latitude, a.longitude, b.latitude, b.longitude),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpx_points_with_times_and_elevation() {
+        let gpx = r#"<gpx><trk><trkseg>
+            <trkpt lat="35.7" lon="51.4"><ele>1200.5</ele><time>2024-01-01T00:00:00Z</time></trkpt>
+            <trkpt lon='51.5' lat='35.8'><time>2024-01-01T00:00:10Z</time></trkpt>
+        </trkseg></trk></gpx>"#;
+        let (points, timed) = parse_gpx(gpx);
+        assert!(timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].latitude, points[0].longitude), (35.7, 51.4));
+        assert_eq!(points[0].altitude, 1200.5);
+        assert_eq!((points[1].latitude, points[1].longitude), (35.8, 51.5));
+        assert_eq!(points[1].time - points[0].time, 10.0);
+    }
+
+    #[test]
+    fn gpx_route_without_times() {
+        let (points, timed) =
+            parse_gpx(r#"<rte><rtept lat="1" lon="2"/><rtept lat="3" lon="4"/></rte>"#);
+        assert!(!timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[1].latitude, points[1].longitude), (3.0, 4.0));
+    }
+
+    #[test]
+    fn kml_coordinates_are_lon_lat_alt() {
+        let kml = "<LineString><coordinates>51.4,35.7,1200 51.5,35.8</coordinates></LineString>";
+        let (points, timed) = parse_kml(kml);
+        assert!(!timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].latitude, points[0].longitude), (35.7, 51.4));
+        assert_eq!(points[0].altitude, 1200.0);
+        assert_eq!(points[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn kml_gx_track_pairs_whens_and_coords() {
+        let kml = "<gx:Track><when>2024-01-01T00:00:00Z</when><when>2024-01-01T00:01:00Z</when>\
+                   <gx:coord>51.4 35.7 10</gx:coord><gx:coord>51.5 35.8 20</gx:coord></gx:Track>";
+        let (points, timed) = parse_kml(kml);
+        assert!(timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].time - points[0].time, 60.0);
+        assert_eq!((points[1].latitude, points[1].altitude), (35.8, 20.0));
+    }
+
+    /// Timestamps of a timed CSV track
+    fn csv_times(csv: &str) -> Vec<f64> {
+        let (points, timed) = parse_csv(csv);
+        assert!(timed);
+        points.iter().map(|p| p.time).collect()
+    }
+
+    #[test]
+    fn csv_with_header_in_any_order() {
+        let csv = "# exported\nLon,Time,Lat,Ele\n51.4,0,35.7,100\n51.5,5,35.8,\nbad,line\n";
+        let (points, timed) = parse_csv(csv);
+        assert!(timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].latitude, points[0].longitude), (35.7, 51.4));
+        assert_eq!(points[0].altitude, 100.0);
+        assert_eq!(points[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn csv_times_in_seconds() {
+        let csv = "time,lat,lon\n10,35.7,51.4\n12.5,35.8,51.5\n";
+        assert_eq!(csv_times(csv), [10.0, 12.5]);
+    }
+
+    #[test]
+    fn csv_times_in_iso_8601_utc() {
+        let csv =
+            "timestamp,lat,lon\n2024-01-01T00:00:00Z,35.7,51.4\n2024-01-01T00:00:05Z,35.8,51.5\n";
+        assert_eq!(csv_times(csv), [1_704_067_200.0, 1_704_067_205.0]);
+    }
+
+    #[test]
+    fn csv_times_in_iso_8601_with_a_space_and_millis() {
+        let csv = "t,lat,lon\n2024-01-01 00:00:00,35.7,51.4\n2024-01-01 00:00:00.250,35.8,51.5\n";
+        assert_eq!(csv_times(csv), [1_704_067_200.0, 1_704_067_200.25]);
+    }
+
+    #[test]
+    fn csv_times_in_iso_8601_with_an_offset() {
+        let csv = "time,lat,lon\n2024-01-01T03:30:00+03:30,35.7,51.4\n2023-12-31T19:00:10-05:00,35.8,51.5\n";
+        assert_eq!(csv_times(csv), [1_704_067_200.0, 1_704_067_210.0]);
+    }
+
+    #[test]
+    fn csv_with_an_unreadable_time_is_untimed() {
+        let (points, timed) = parse_csv("time,lat,lon\n10,35.7,51.4\nnoon,35.8,51.5\n");
+        assert!(!timed);
+        assert_eq!(points.len(), 2);
+    }
+
+    #[test]
+    fn csv_without_header_is_lat_lon_alt() {
+        let (points, timed) = parse_csv("35.7,51.4,5\n35.8,51.5\n");
+        assert!(!timed);
+        assert_eq!(points.len(), 2);
+        assert_eq!(
+            (points[0].latitude, points[0].longitude, points[0].altitude),
+            (35.7, 51.4, 5.0)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serialport::SerialPort;
+#[cfg(unix)]
+use serialport::TTYPort;
+
+use crate::sim868::gnss::GnssConfiguration;
+
+/// What the GNSS UART thread is asked to do
+pub enum UartMessage {
+    Data(String),
+    Baud(u32),
+}
+
+/// Where the GNSS UART of the module is exposed
+enum Transport {
+    Serial(Box<dyn SerialPort>),
+    /// the master side, the slave is kept open so writes never fail while nobody listens
+    #[cfg(unix)]
+    Pty {
+        master: TTYPort,
+        _slave: TTYPort,
+    },
+    Tcp(TcpListener, Vec<TcpStream>),
+}
+
+impl Transport {
+    /// `tcp:<address>:<port>`, `pty[:<link>]` or the path of a serial device, along with where
+    /// the host finds it
+    fn open(spec: &str, baud: u32) -> Result<(Transport, String), String> {
+        if let Some(address) = spec.strip_prefix("tcp:") {
+            let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+            listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+            return Ok((Transport::Tcp(listener, vec![]), spec.to_owned()));
+        }
+        if let Some(link) = spec.strip_prefix("pty") {
+            return Transport::open_pty(link);
+        }
+        let port = serialport::new(spec, baud)
+            .timeout(Duration::from_millis(10))
+            .open()
+            .map_err(|e| e.to_string())?;
+        Ok((Transport::Serial(port), spec.to_owned()))
+    }
+
+    #[cfg(unix)]
+    fn open_pty(link: &str) -> Result<(Transport, String), String> {
+        let (mut master, slave) = TTYPort::pair().map_err(|e| e.to_string())?;
+        master
+            .set_timeout(Duration::from_millis(10))
+            .map_err(|e| e.to_string())?;
+        let mut name = slave.name().unwrap_or_default();
+        if let Some(link) = link.strip_prefix(':') {
+            let _ = std::fs::remove_file(link);
+            std::os::unix::fs::symlink(&name, link).map_err(|e| e.to_string())?;
+            name = format!("{} -> {}", link, name);
+        }
+        let pty = Transport::Pty {
+            master,
+            _slave: slave,
+        };
+        Ok((pty, name))
+    }
+
+    #[cfg(not(unix))]
+    fn open_pty(_link: &str) -> Result<(Transport, String), String> {
+        Err("pseudo terminals are only supported on unix".to_owned())
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        match self {
+            Transport::Serial(port) => {
+                let _ = port.write_all(data);
+            }
+            #[cfg(unix)]
+            Transport::Pty { master, .. } => {
+                let _ = master.write_all(data);
+            }
+            Transport::Tcp(_, clients) => clients.retain_mut(|c| c.write_all(data).is_ok()),
+        }
+    }
+
+    /// Whatever the host sent since the last call
+    fn read(&mut self) -> Vec<u8> {
+        let mut buffer = [0u8; 256];
+        let mut received = vec![];
+        match self {
+            Transport::Serial(port) => {
+                if let Ok(n) = port.read(&mut buffer) {
+                    received.extend_from_slice(&buffer[..n]);
+                }
+            }
+            #[cfg(unix)]
+            Transport::Pty { master, .. } => {
+                if let Ok(n) = master.read(&mut buffer) {
+                    received.extend_from_slice(&buffer[..n]);
+                }
+            }
+            Transport::Tcp(listener, clients) => {
+                while let Ok((client, _)) = listener.accept() {
+                    if client.set_nonblocking(true).is_ok() {
+                        clients.push(client);
+                    }
+                }
+                clients.retain_mut(|c| match c.read(&mut buffer) {
+                    Ok(0) => false,
+                    Ok(n) => {
+                        received.extend_from_slice(&buffer[..n]);
+                        true
+                    }
+                    Err(e) => e.kind() == ErrorKind::WouldBlock,
+                });
+            }
+        }
+        received
+    }
+
+    fn set_baud(&mut self, baud: u32) {
+        match self {
+            Transport::Serial(port) => {
+                let _ = port.set_baud_rate(baud);
+            }
+            #[cfg(unix)]
+            Transport::Pty { master, .. } => {
+                let _ = master.set_baud_rate(baud);
+            }
+            Transport::Tcp(..) => {}
+        }
+    }
+}
+
+/// Opens the transport of `gnss.port` and serves it from its own thread: NMEA goes out at the pace
+/// of the UART baud rate, PMTK commands coming in are handed to the GNSS model. Returns where the
+/// host finds the UART, or why it could not be opened
+pub fn open(
+    spec: &str,
+    baud: u32,
+    gnss: Arc<Mutex<GnssConfiguration>>,
+) -> Result<(Sender<UartMessage>, String), String> {
+    let (mut transport, name) = Transport::open(spec, baud)?;
+    let (tx, rx): (Sender<UartMessage>, Receiver<UartMessage>) = channel();
+    std::thread::spawn(move || {
+        let mut baud = baud;
+        let mut line = vec![];
+        loop {
+            match rx.recv_timeout(Duration::from_millis(20)) {
+                Ok(UartMessage::Data(data)) => {
+                    transport.write(data.as_bytes());
+                    // a real UART only has room for baud / 10 characters a second
+                    if !matches!(transport, Transport::Serial(_)) {
+                        let seconds = data.len() as f64 * 10.0 / baud.max(1) as f64;
+                        std::thread::sleep(Duration::from_secs_f64(seconds));
+                    }
+                }
+                Ok(UartMessage::Baud(rate)) => {
+                    baud = rate;
+                    transport.set_baud(rate);
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(_) => return,
+            }
+            for byte in transport.read() {
+                if byte == b'\n' {
+                    let command = String::from_utf8_lossy(&line).trim().to_owned();
+                    line.clear();
+                    if command.starts_with('$') {
+                        let replies = gnss.lock().unwrap().pmtk(&command);
+                        for reply in replies {
+                            transport.write(reply.as_bytes());
+                        }
+                    }
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+    });
+    Ok((tx, name))
+}
@@ -0,0 +1,491 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    config::Config,
+    sim868::{bytes, dns, params, PendingInput, Sim868, ERROR, OK},
+    utils::serial::PortControl,
+};
+
+const AT_HTTPINIT: &str = "AT+HTTPINIT";
+const AT_HTTPPARA: &str = "AT+HTTPPARA";
+const AT_HTTPDATA: &str = "AT+HTTPDATA";
+const AT_HTTPACTION: &str = "AT+HTTPACTION";
+const AT_HTTPREAD: &str = "AT+HTTPREAD";
+const AT_HTTPTERM: &str = "AT+HTTPTERM";
+const AT_HTTPSSL: &str = "AT+HTTPSSL";
+const AT_SSLOPT: &str = "AT+SSLOPT";
+
+pub const HTTP_NETWORK_ERROR: u16 = 601;
+pub const HTTP_DNS_ERROR: u16 = 603;
+pub const HTTP_STACK_BUSY: u16 = 604;
+pub const HTTP_SSL_HANDSHAKE_ERROR: u16 = 605;
+pub const HTTP_SSL_CERTIFICATE_ERROR: u16 = 606;
+
+/// largest body the module accepts through AT+HTTPDATA
+const MAX_HTTP_DATA: usize = 319488;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// What AT+HTTPPARA and AT+HTTPDATA collected for the next action
+#[derive(Clone)]
+pub struct HttpRequest {
+    pub url: String,
+    pub content: String,
+    pub userdata: String,
+    pub ua: String,
+    pub timeout: u64,
+    pub data: Vec<u8>,
+}
+
+/// AT+SSLOPT switches, they outlive the HTTP sessions
+#[derive(Clone, Copy)]
+pub struct SslOptions {
+    pub ignore_invalid_cert: bool,
+    pub client_auth: bool,
+}
+
+impl SslOptions {
+    pub fn new() -> SslOptions {
+        SslOptions {
+            ignore_invalid_cert: false,
+            client_auth: false,
+        }
+    }
+}
+
+pub struct Http {
+    pub initialized: bool,
+    pub ssl: bool,
+    pub cid: usize,
+    pub request: HttpRequest,
+    pub response: Arc<Mutex<Option<HttpResponse>>>,
+    busy: Arc<AtomicBool>,
+}
+
+impl Http {
+    pub fn new() -> Http {
+        Http {
+            initialized: false,
+            ssl: false,
+            cid: 1,
+            request: HttpRequest {
+                url: String::new(),
+                content: String::new(),
+                userdata: String::new(),
+                ua: "SIMCOM_MODULE".to_owned(),
+                timeout: 120,
+                data: vec![],
+            },
+            response: Arc::new(Mutex::new(None)),
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+fn method_name(method: u8) -> &'static str {
+    match method {
+        1 => "POST",
+        2 => "HEAD",
+        _ => "GET",
+    }
+}
+
+/// Splits `[http[s]://]host[:port][/path]` into its parts, the module accepts URLs without a scheme
+pub fn split_url(url: &str) -> Option<(String, u16, String)> {
+    let (rest, default_port) = match url.strip_prefix("https://") {
+        Some(rest) => (rest, 443),
+        None => (url.strip_prefix("http://").unwrap_or(url), 80),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().ok()?),
+        None => (authority, default_port),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port, path.to_owned()))
+}
+
+/// Looks the request up in the `http.mocks` file, lines read `<METHOD|*> <url> <status> [<body>|@<file>]`
+fn mock_response(config: &Config, method: u8, url: &str) -> Option<HttpResponse> {
+    let content = fs::read_to_string(config.get("http.mocks")?).ok()?;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(4, ' ');
+        let (Some(m), Some(u), Some(status)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if (m != "*" && m != method_name(method)) || u != url {
+            continue;
+        }
+        let body = match fields.next().unwrap_or("") {
+            b if b.starts_with('@') => fs::read(&b[1..]).unwrap_or_default(),
+            b => b.as_bytes().to_vec(),
+        };
+        return Some(HttpResponse {
+            status: status.parse().unwrap_or(200),
+            body,
+        });
+    }
+    None
+}
+
+/// Sends the request over `stream` with HTTP/1.0 so the server closes when the body is done
+fn exchange<S: Read + Write>(
+    mut stream: S,
+    method: u8,
+    host: &str,
+    path: &str,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let mut head = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\nConnection: close\r\n",
+        method_name(method),
+        path,
+        host,
+        request.ua
+    );
+    if !request.userdata.is_empty() {
+        head += &request.userdata.replace("\\r\\n", "\r\n");
+        head += "\r\n";
+    }
+    if method == 1 {
+        if !request.content.is_empty() {
+            head += &format!("Content-Type: {}\r\n", request.content);
+        }
+        head += &format!("Content-Length: {}\r\n", request.data.len());
+    }
+    head += "\r\n";
+    let mut raw = vec![];
+    let sent = stream.write_all(head.as_bytes()).is_ok()
+        && (method != 1 || stream.write_all(&request.data).is_ok());
+    if !sent || stream.read_to_end(&mut raw).is_err() && raw.is_empty() {
+        return HttpResponse {
+            status: HTTP_NETWORK_ERROR,
+            body: vec![],
+        };
+    }
+    let Some(split) = raw.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return HttpResponse {
+            status: HTTP_NETWORK_ERROR,
+            body: vec![],
+        };
+    };
+    let status = String::from_utf8_lossy(&raw[..split])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .unwrap_or(HTTP_NETWORK_ERROR);
+    let body = if method == 2 {
+        vec![]
+    } else {
+        raw[split + 4..].to_vec()
+    };
+    HttpResponse { status, body }
+}
+
+fn tls_connector(config: &Config, ssl: SslOptions) -> Option<native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(ssl.ignore_invalid_cert);
+    if let Some(path) = config.get("https.ca_file") {
+        let pem = fs::read(path).ok()?;
+        builder.add_root_certificate(native_tls::Certificate::from_pem(&pem).ok()?);
+    }
+    builder.build().ok()
+}
+
+/// Code of a handshake that failed with certificate checks on. native-tls reports no kind of
+/// error, so the handshake is tried again without the checks: when that one goes through the
+/// certificate was to blame.
+fn handshake_error(config: &Config, host: &str, connect: impl Fn() -> Option<TcpStream>) -> u16 {
+    let lenient = SslOptions {
+        ignore_invalid_cert: true,
+        client_auth: false,
+    };
+    let certificate_rejected = tls_connector(config, lenient)
+        .zip(connect())
+        .is_some_and(|(connector, stream)| connector.connect(host, stream).is_ok());
+    if certificate_rejected {
+        HTTP_SSL_CERTIFICATE_ERROR
+    } else {
+        HTTP_SSL_HANDSHAKE_ERROR
+    }
+}
+
+/// `ssl` is set when the session enabled AT+HTTPSSL
+fn perform(
+    config: &Config,
+    method: u8,
+    request: &HttpRequest,
+    ssl: Option<SslOptions>,
+) -> HttpResponse {
+    if let Some(ssl) = ssl {
+        // scripted failures come first so retry logic can be tested against any server
+        match config.get("https.fail") {
+            Some("handshake") => return failed(HTTP_SSL_HANDSHAKE_ERROR),
+            // a module told to ignore invalid certificates never rejects one
+            Some("certificate") if !ssl.ignore_invalid_cert => {
+                return failed(HTTP_SSL_CERTIFICATE_ERROR)
+            }
+            _ => {}
+        }
+    }
+    if let Some(response) = mock_response(config, method, &request.url) {
+        std::thread::sleep(Duration::from_millis(
+            config.get_or("http.mock_delay_ms", 500),
+        ));
+        return response;
+    }
+    let Some((host, port, path)) = split_url(&request.url) else {
+        return failed(HTTP_NETWORK_ERROR);
+    };
+    let Some(ip) = dns::resolve(config, &host)
+        .ok()
+        .and_then(|ips| ips.first().and_then(|ip| ip.parse().ok()))
+    else {
+        return failed(HTTP_DNS_ERROR);
+    };
+    let timeout = Duration::from_secs(request.timeout.max(1));
+    let connect = || {
+        let stream = TcpStream::connect_timeout(&SocketAddr::new(ip, port), timeout).ok()?;
+        stream.set_read_timeout(Some(timeout)).ok()?;
+        Some(stream)
+    };
+    let Some(stream) = connect() else {
+        return failed(HTTP_NETWORK_ERROR);
+    };
+    let Some(ssl) = ssl else {
+        return exchange(stream, method, &host, &path, request);
+    };
+    let Some(connector) = tls_connector(config, ssl) else {
+        return failed(HTTP_SSL_HANDSHAKE_ERROR);
+    };
+    match connector.connect(&host, stream) {
+        Ok(tls) => exchange(tls, method, &host, &path, request),
+        Err(_) if ssl.ignore_invalid_cert => failed(HTTP_SSL_HANDSHAKE_ERROR),
+        Err(_) => failed(handshake_error(config, &host, connect)),
+    }
+}
+
+fn failed(status: u16) -> HttpResponse {
+    HttpResponse {
+        status,
+        body: vec![],
+    }
+}
+
+impl Sim868 {
+    pub fn http_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+        let answer = if line.starts_with(AT_HTTPINIT) {
+            self.httpinit()
+        } else if line.starts_with(AT_SSLOPT) {
+            self.sslopt(line)
+        } else if !line.starts_with("AT+HTTP") {
+            return None;
+        } else if !self.http.initialized {
+            vec![at!(ERROR)]
+        } else if line.starts_with(AT_HTTPPARA) {
+            self.httppara(line)
+        } else if line.starts_with(AT_HTTPDATA) {
+            self.httpdata(line)
+        } else if line.starts_with(AT_HTTPACTION) {
+            self.httpaction(line, tx)
+        } else if line.starts_with(AT_HTTPREAD) {
+            return Some(self.httpread(line));
+        } else if line.starts_with(AT_HTTPSSL) {
+            self.httpssl(line)
+        } else if line.starts_with(AT_HTTPTERM) {
+            self.http.initialized = false;
+            vec![at!(OK)]
+        } else {
+            return None;
+        };
+        Some(bytes(answer))
+    }
+
+    fn httpinit(&mut self) -> Vec<String> {
+        if self.http.initialized {
+            return vec![at!(ERROR)];
+        }
+        // a fresh response slot, so a late answer of a terminated session never shows up here
+        self.http = Http::new();
+        self.http.initialized = true;
+        vec![at!(OK)]
+    }
+
+    /// `AT+HTTPPARA=<param>,<value>`
+    fn httppara(&mut self, line: &str) -> Vec<String> {
+        let args = params(line, AT_HTTPPARA);
+        let (Some(param), Some(value)) = (args.first(), args.get(1)) else {
+            return vec![at!(ERROR)];
+        };
+        let request = &mut self.http.request;
+        match param.to_uppercase().as_str() {
+            "CID" => match value.parse::<usize>() {
+                Ok(cid) => self.http.cid = cid,
+                Err(_) => return vec![at!(ERROR)],
+            },
+            "URL" => request.url = value.clone(),
+            "CONTENT" => request.content = value.clone(),
+            "USERDATA" => request.userdata = value.clone(),
+            "UA" => request.ua = value.clone(),
+            "TIMEOUT" => match value.parse::<u64>() {
+                Ok(t) if (30..=1000).contains(&t) => request.timeout = t,
+                _ => return vec![at!(ERROR)],
+            },
+            "REDIR" | "BREAK" | "BREAKEND" | "PROIP" | "PROPORT" => {}
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+HTTPDATA=<size>,<time>`, the body follows the DOWNLOAD prompt
+    fn httpdata(&mut self, line: &str) -> Vec<String> {
+        let args = params(line, AT_HTTPDATA);
+        match args.first().and_then(|a| a.parse::<usize>().ok()) {
+            Some(0) => {
+                self.http.request.data.clear();
+                vec![at!(OK)]
+            }
+            Some(size) if size <= MAX_HTTP_DATA => {
+                self.expect_data(PendingInput::HttpData, PortControl::ReadExact(size));
+                vec![at!("DOWNLOAD")]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    pub fn httpdata_received(&mut self, data: &[u8]) -> Vec<String> {
+        self.http.request.data = data.to_vec();
+        vec![at!(OK)]
+    }
+
+    /// `AT+HTTPACTION=<method>`, the result comes later as `+HTTPACTION: <method>,<status>,<len>`
+    fn httpaction(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let Some(method) = params(line, AT_HTTPACTION)
+            .first()
+            .and_then(|a| a.parse::<u8>().ok())
+            .filter(|m| *m <= 2)
+        else {
+            return vec![at!(ERROR)];
+        };
+        if self.http.busy.swap(true, Ordering::SeqCst) {
+            return vec![
+                at!(OK),
+                at!(format!("+HTTPACTION: {},{},0", method, HTTP_STACK_BUSY)),
+            ];
+        }
+        let bearer_open = self.open_bearer(self.http.cid).is_some();
+        let ssl = self.http.ssl.then_some(self.ssl_options);
+        let config = self.config.clone();
+        let request = self.http.request.clone();
+        let response = self.http.response.clone();
+        let busy = self.http.busy.clone();
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let result = if bearer_open {
+                perform(&config, method, &request, ssl)
+            } else {
+                failed(HTTP_NETWORK_ERROR)
+            };
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                busy.store(false, Ordering::SeqCst);
+                return;
+            }
+            let urc = format!(
+                "+HTTPACTION: {},{},{}",
+                method,
+                result.status,
+                result.body.len()
+            );
+            *response.lock().unwrap() = Some(result);
+            busy.store(false, Ordering::SeqCst);
+            let _ = tx.send(at!(urc).into());
+        });
+        vec![at!(OK)]
+    }
+
+    fn httpssl(&mut self, line: &str) -> Vec<String> {
+        if line[AT_HTTPSSL.len()..].starts_with('?') {
+            return vec![at!(format!("+HTTPSSL: {}", self.http.ssl as u8)), at!(OK)];
+        }
+        match params(line, AT_HTTPSSL).first().map(|p| p.as_str()) {
+            Some("0") => self.http.ssl = false,
+            Some("1") => self.http.ssl = true,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+SSLOPT=<opt>,<enable>`, opt 0 ignores invalid certificates, opt 1 is client authentication
+    fn sslopt(&mut self, line: &str) -> Vec<String> {
+        if line[AT_SSLOPT.len()..].starts_with('?') {
+            return vec![
+                at!(format!(
+                    "+SSLOPT: 0,{}\r\n+SSLOPT: 1,{}",
+                    self.ssl_options.ignore_invalid_cert as u8, self.ssl_options.client_auth as u8
+                )),
+                at!(OK),
+            ];
+        }
+        let args = params(line, AT_SSLOPT);
+        let enable = match args.get(1).map(|a| a.as_str()) {
+            Some("0") => false,
+            Some("1") => true,
+            _ => return vec![at!(ERROR)],
+        };
+        match args.first().map(|a| a.as_str()) {
+            Some("0") => self.ssl_options.ignore_invalid_cert = enable,
+            Some("1") => self.ssl_options.client_auth = enable,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+HTTPREAD` or `AT+HTTPREAD=<start>,<size>` for paging through a long body, the body
+    /// goes out byte for byte
+    fn httpread(&mut self, line: &str) -> Vec<Vec<u8>> {
+        let response = self.http.response.lock().unwrap();
+        let Some(response) = response.as_ref() else {
+            return bytes(vec![at!(ERROR)]);
+        };
+        let args = params(line, AT_HTTPREAD);
+        let (start, size) = match (args.first(), args.get(1)) {
+            (Some(start), Some(size)) => match (start.parse::<usize>(), size.parse::<usize>()) {
+                (Ok(start), Ok(size)) => (start, size),
+                _ => return bytes(vec![at!(ERROR)]),
+            },
+            (None, None) => (0, response.body.len()),
+            _ => return bytes(vec![at!(ERROR)]),
+        };
+        let start = start.min(response.body.len());
+        let end = start.saturating_add(size).min(response.body.len());
+        let chunk = &response.body[start..end];
+        vec![
+            at!(format!("+HTTPREAD: {}", chunk.len())).into_bytes(),
+            chunk.to_vec(),
+            at!(OK).into_bytes(),
+        ]
+    }
+}
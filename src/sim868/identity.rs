@@ -0,0 +1,167 @@
+use crate::{
+    config::Config,
+    sim868::{Sim868, ERROR, OK},
+};
+
+const AT_CGMI: &str = "AT+CGMI";
+const AT_CGMM: &str = "AT+CGMM";
+const AT_CGMR: &str = "AT+CGMR";
+const AT_CGSN: &str = "AT+CGSN";
+const AT_GSN: &str = "AT+GSN";
+const ATI: &str = "ATI";
+
+/// IMEI of the module before the check digit, TAC 86737803
+const DEFAULT_IMEI: &str = "86737803397915";
+
+/// What the module says it is, one firmware the devices meet in the field
+pub struct Identity {
+    pub manufacturer: String,
+    pub model: String,
+    pub revision: String,
+    pub imei: String,
+    pub ati: String,
+}
+
+impl Identity {
+    /// Keys from `identity.file` when one is given, the `identity.*` keys of the
+    /// configuration otherwise. Also returns what was wrong with them, for the log.
+    pub fn from_config(config: &Config) -> (Identity, Vec<String>) {
+        let mut problems = vec![];
+        let (profile, prefix) = match config.get("identity.file") {
+            Some(path) => {
+                if std::fs::metadata(path).is_err() {
+                    problems.push(format!("identity.file {} cannot be read", path));
+                }
+                (Config::load(path), "")
+            }
+            None => (config.clone(), "identity."),
+        };
+        let get = |key: &str, default: &str| {
+            profile
+                .get(&format!("{prefix}{key}"))
+                .unwrap_or(default)
+                .to_string()
+        };
+        let (imei, problem) = imei(&get("imei", DEFAULT_IMEI));
+        problems.extend(problem);
+        let identity = Identity {
+            manufacturer: get("manufacturer", "SIMCOM_Ltd"),
+            model: get("model", "SIMCOM_SIM868"),
+            revision: get("revision", "1418B05Scustome"),
+            imei,
+            ati: get("ati", "SIM868 R14.18"),
+        };
+        (identity, problems)
+    }
+}
+
+/// A 14 digit IMEI gets its Luhn check digit and a 15 digit one has it corrected, anything else
+/// gives way to the default; the second value says what was wrong
+fn imei(digits: &str) -> (String, Option<String>) {
+    let fallback = |why: &str| {
+        let default = imei(DEFAULT_IMEI).0;
+        let problem = format!("IMEI {} {}, using {}", digits, why, default);
+        (default, Some(problem))
+    };
+    if !digits.bytes().all(|d| d.is_ascii_digit()) {
+        return fallback("is not all digits");
+    }
+    match digits.len() {
+        14 => (format!("{digits}{}", luhn_digit(digits)), None),
+        15 => {
+            let full = format!("{}{}", &digits[..14], luhn_digit(&digits[..14]));
+            if full == digits {
+                (full, None)
+            } else {
+                let problem = format!("IMEI {} has a wrong check digit, using {}", digits, full);
+                (full, Some(problem))
+            }
+        }
+        _ => fallback("is neither 14 nor 15 digits"),
+    }
+}
+
+/// Check digit that makes `digits` followed by it pass the Luhn test
+fn luhn_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            let d = (d - b'0') as u32;
+            if i % 2 == 0 {
+                let doubled = d * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                d
+            }
+        })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+impl Sim868 {
+    /// `AT+CGMI`, `AT+CGMM`, `AT+CGMR`, `AT+CGSN`, `AT+GSN` and `ATI`, blanks around the
+    /// command do not matter
+    pub fn identity_command(&mut self, line: &str) -> Option<Vec<String>> {
+        let line = line.trim();
+        let (cmd, rest) = [AT_CGMI, AT_CGMM, AT_CGMR, AT_CGSN, AT_GSN, ATI]
+            .iter()
+            .find_map(|cmd| line.strip_prefix(cmd).map(|rest| (*cmd, rest.trim())))?;
+        match rest {
+            "=?" if cmd != ATI => return Some(vec![at!(OK)]),
+            "" => (),
+            "0" if cmd == ATI => (),
+            _ => return Some(vec![at!(ERROR)]),
+        }
+        let identity = &self.identity;
+        let answer = match cmd {
+            AT_CGMI => &identity.manufacturer,
+            AT_CGMM => &identity.model,
+            AT_CGMR => &identity.revision,
+            AT_CGSN | AT_GSN => &identity.imei,
+            _ => &identity.ati,
+        };
+        Some(vec![at!(answer), at!(OK)])
+    }
+
+    /// Reads the identity once the configuration is in place, returns what was wrong with it
+    pub fn load_identity(&mut self) -> Vec<String> {
+        let (identity, problems) = Identity::from_config(&self.config);
+        self.identity = identity;
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luhn_digit_of_a_known_imei() {
+        assert_eq!(luhn_digit("49015420323751"), 8);
+        assert_eq!(luhn_digit("35209900176148"), 1);
+    }
+
+    #[test]
+    fn imei_gets_or_corrects_its_check_digit() {
+        assert_eq!(imei("49015420323751"), ("490154203237518".to_owned(), None));
+        assert_eq!(
+            imei("490154203237518"),
+            ("490154203237518".to_owned(), None)
+        );
+        let (corrected, problem) = imei("490154203237510");
+        assert_eq!(corrected, "490154203237518");
+        assert!(problem.is_some());
+    }
+
+    #[test]
+    fn malformed_imei_falls_back_to_the_default() {
+        let default = format!("{}{}", DEFAULT_IMEI, luhn_digit(DEFAULT_IMEI));
+        for malformed in ["", "1234", "4901542032375x", "4901542032375180"] {
+            let (imei, problem) = imei(malformed);
+            assert_eq!(imei, default);
+            assert!(problem.is_some());
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    sim868::{params, Sim868, ERROR, OK},
+    utils::serial::{PortControl, FLOW_HARDWARE},
+};
+
+const AT_AND_D: &str = "AT&D";
+const AT_AND_C: &str = "AT&C";
+const AT_CFGRI: &str = "AT+CFGRI";
+const AT_IFC: &str = "AT+IFC";
+
+/// How long RI stays active for a URC
+const RI_PULSE: Duration = Duration::from_millis(120);
+
+/// How the module treats its modem control lines
+pub struct Modem {
+    /// `AT&D`: 0 ignores DTR, 1 leaves data mode when it drops, 2 also hangs up
+    pub dtr_mode: u8,
+    /// `AT&C`: 0 keeps DCD on, 1 turns it on only while a connection is up
+    pub dcd_mode: u8,
+    /// `AT+CFGRI=1` pulses RI on URCs and received data
+    pub ri_on_urc: Arc<AtomicBool>,
+    /// DTR as the last poll saw it, to catch it dropping
+    pub last_dtr: bool,
+    /// the forwarding thread URCs go through, it pulses RI
+    pub urc_tx: Option<Sender<Vec<u8>>>,
+    /// when the forwarding thread last passed a URC or received data, it wakes the module
+    pub last_urc: Arc<Mutex<Instant>>,
+}
+
+impl Modem {
+    pub fn new() -> Modem {
+        Modem {
+            dtr_mode: 1,
+            dcd_mode: 1,
+            ri_on_urc: Arc::new(AtomicBool::new(false)),
+            last_dtr: true,
+            urc_tx: None,
+            last_urc: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl Sim868 {
+    pub fn modem_command(&mut self, line: &str) -> Option<Vec<String>> {
+        if line.starts_with(AT_AND_D) {
+            Some(self.and_d(line))
+        } else if line.starts_with(AT_AND_C) {
+            Some(self.and_c(line))
+        } else if line.starts_with(AT_CFGRI) {
+            Some(self.cfgri(line))
+        } else if line.starts_with(AT_IFC) {
+            Some(self.ifc(line))
+        } else {
+            None
+        }
+    }
+
+    /// The sender command handlers hand their URCs to, every URC through it wakes the module and
+    /// pulses RI when AT+CFGRI=1
+    pub fn urc_channel(&mut self, port_tx: Sender<Vec<u8>>) -> Sender<Vec<u8>> {
+        if let Some(urc_tx) = &self.modem.urc_tx {
+            return urc_tx.clone();
+        }
+        let (urc_tx, urc_rx) = channel::<Vec<u8>>();
+        let ri_on_urc = self.modem.ri_on_urc.clone();
+        let lines = self.lines.clone();
+        let last_urc = self.modem.last_urc.clone();
+        std::thread::spawn(move || {
+            let mut release: Option<Instant> = None;
+            loop {
+                let timeout = release
+                    .map(|r| r.saturating_duration_since(Instant::now()))
+                    .unwrap_or(Duration::from_secs(3600));
+                match urc_rx.recv_timeout(timeout) {
+                    Ok(urc) => {
+                        *last_urc.lock().unwrap() = Instant::now();
+                        if ri_on_urc.load(Ordering::Relaxed) {
+                            lines.ri.store(true, Ordering::Relaxed);
+                            release = Some(Instant::now() + RI_PULSE);
+                        }
+                        if port_tx.send(urc).is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        lines.ri.store(false, Ordering::Relaxed);
+                        release = None;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        self.modem.urc_tx = Some(urc_tx.clone());
+        urc_tx
+    }
+
+    /// Brings CTS and DCD up to date and acts on DTR dropping, called every turn of the UI loop
+    pub fn poll_lines(&mut self, tx: Sender<Vec<u8>>) {
+        let dtr = self.lines.dtr.load(Ordering::Relaxed);
+        let dropped = self.modem.last_dtr && !dtr;
+        self.modem.last_dtr = dtr;
+        if dropped && self.power && self.modem.dtr_mode > 0 {
+            let mut answer = vec![];
+            if self.pending_input.take().is_some() {
+                if let Some(ctrl) = &self.port_ctrl {
+                    let _ = ctrl.send(PortControl::ReadLines);
+                }
+                answer.push(at!(OK));
+            }
+            if self.modem.dtr_mode == 2 {
+                answer.extend(self.hang_up());
+            }
+            for line in answer {
+                let _ = tx.send(line.into());
+            }
+        }
+        // the port thread also drops CTS while the input buffer is filling up, without this lag
+        let ready = self.power && !self.asleep();
+        let carrier = self.power && (self.modem.dcd_mode == 0 || self.tcpip.any_connected());
+        self.lines.cts.store(ready, Ordering::Relaxed);
+        self.lines.dcd.store(carrier, Ordering::Relaxed);
+    }
+
+    /// `AT&D[<n>]`
+    fn and_d(&mut self, line: &str) -> Vec<String> {
+        match line[AT_AND_D.len()..].trim() {
+            "" | "0" => self.modem.dtr_mode = 0,
+            "1" => self.modem.dtr_mode = 1,
+            "2" => self.modem.dtr_mode = 2,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT&C[<n>]`
+    fn and_c(&mut self, line: &str) -> Vec<String> {
+        match line[AT_AND_C.len()..].trim() {
+            "" | "0" => self.modem.dcd_mode = 0,
+            "1" => self.modem.dcd_mode = 1,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    fn cfgri(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CFGRI.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+CFGRI: (0,1)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            let status = self.modem.ri_on_urc.load(Ordering::Relaxed) as u8;
+            return vec![at!(format!("+CFGRI: {}", status)), at!(OK)];
+        }
+        match params(line, AT_CFGRI).first().map(|s| s.as_str()) {
+            Some("0") => self.modem.ri_on_urc.store(false, Ordering::Relaxed),
+            Some("1") => self.modem.ri_on_urc.store(true, Ordering::Relaxed),
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+IFC=<dce_by_dte>[,<dte_by_dce>]`: 0 none, 1 XON/XOFF, 2 RTS/CTS
+    fn ifc(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_IFC.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+IFC: (0-2),(0-2)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![
+                at!(format!(
+                    "+IFC: {},{}",
+                    self.lines.output_flow.load(Ordering::Relaxed),
+                    self.lines.input_flow.load(Ordering::Relaxed)
+                )),
+                at!(OK),
+            ];
+        }
+        let args = params(line, AT_IFC);
+        let flow = |i: usize| {
+            args.get(i)
+                .and_then(|f| f.parse::<u8>().ok())
+                .filter(|f| *f <= FLOW_HARDWARE)
+        };
+        let Some(output_flow) = flow(0) else {
+            return vec![at!(ERROR)];
+        };
+        let input_flow = match args.get(1) {
+            None => output_flow,
+            Some(_) => match flow(1) {
+                Some(f) => f,
+                None => return vec![at!(ERROR)],
+            },
+        };
+        self.lines.output_flow.store(output_flow, Ordering::Relaxed);
+        self.lines.input_flow.store(input_flow, Ordering::Relaxed);
+        vec![at!(OK)]
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{atomic::Ordering, mpsc::Sender},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::Config,
+    sim868::{bearer::BEARER_PROFILES, dns, params, rtc::ZONE_RANGE, Sim868, ERROR, OK},
+    utils::time,
+};
+
+const AT_CNTPCID: &str = "AT+CNTPCID";
+const AT_CNTP: &str = "AT+CNTP";
+
+pub const NTP_SUCCESS: u8 = 1;
+pub const NTP_NETWORK_ERROR: u8 = 61;
+pub const NTP_DNS_ERROR: u8 = 62;
+pub const NTP_CONNECT_ERROR: u8 = 63;
+pub const NTP_RESPONSE_ERROR: u8 = 64;
+pub const NTP_TIMEOUT: u8 = 65;
+
+/// longest server name AT+CNTP takes
+const NTP_SERVER_MAX: usize = 126;
+/// seconds between the NTP era, 1900, and the unix epoch
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+
+pub struct Ntp {
+    pub cid: usize,
+    pub server: String,
+    /// zone the synchronized RTC gets, in quarter hours
+    pub zone: i32,
+}
+
+impl Ntp {
+    pub fn new() -> Ntp {
+        Ntp {
+            cid: 1,
+            server: String::new(),
+            zone: 0,
+        }
+    }
+}
+
+/// One SNTP exchange with `server`, the UTC it answered or the `+CNTP` error code
+fn sntp(server: SocketAddr, timeout: Duration) -> Result<f64, u8> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|_| NTP_CONNECT_ERROR)?;
+    socket.connect(server).map_err(|_| NTP_CONNECT_ERROR)?;
+    socket
+        .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+        .map_err(|_| NTP_CONNECT_ERROR)?;
+    // version 4, client mode
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    let sent = Instant::now();
+    socket.send(&request).map_err(|_| NTP_CONNECT_ERROR)?;
+    let mut reply = [0u8; 48];
+    let len = socket.recv(&mut reply).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => NTP_TIMEOUT,
+        _ => NTP_CONNECT_ERROR,
+    })?;
+    let transmit = transmit_time(&reply[..len])?;
+    Ok(transmit + sent.elapsed().as_secs_f64() / 2.0)
+}
+
+/// The transmit timestamp of an SNTP reply as Unix time, `NTP_RESPONSE_ERROR` for anything
+/// but a full server reply
+fn transmit_time(reply: &[u8]) -> Result<f64, u8> {
+    // a server in mode 4 with a stratum, a kiss-o'-death has none
+    if reply.len() < 48 || reply[0] & 0x07 != 4 || reply[1] == 0 {
+        return Err(NTP_RESPONSE_ERROR);
+    }
+    let seconds = u32::from_be_bytes(reply[40..44].try_into().unwrap());
+    let fraction = u32::from_be_bytes(reply[44..48].try_into().unwrap());
+    if seconds == 0 {
+        return Err(NTP_RESPONSE_ERROR);
+    }
+    Ok(f64::from(seconds) - NTP_UNIX_OFFSET + f64::from(fraction) / 4_294_967_296.0)
+}
+
+/// `ntp.result` when it is a code `+CNTP` reports, anything else leaves the query to the server
+fn scripted_result(value: Option<&str>) -> Option<u8> {
+    value?
+        .parse()
+        .ok()
+        .filter(|code| *code == NTP_SUCCESS || (NTP_NETWORK_ERROR..=NTP_TIMEOUT).contains(code))
+}
+
+/// What the synchronization ends with: `ntp.result` when scripted, the answer of the server
+/// the emulated DNS points `server` to otherwise
+fn synchronize(config: &Config, server: &str) -> Result<f64, u8> {
+    match scripted_result(config.get("ntp.result")) {
+        None => (),
+        Some(NTP_SUCCESS) => return Ok(time::now()),
+        Some(code) => return Err(code),
+    }
+    let ip = dns::resolve(config, server)
+        .ok()
+        .and_then(|ips| ips.first().and_then(|ip| ip.parse::<IpAddr>().ok()))
+        .ok_or(NTP_DNS_ERROR)?;
+    let port = config.get_or("ntp.port", 123);
+    let timeout = Duration::from_millis(config.get_or("ntp.timeout_ms", 5000));
+    sntp(SocketAddr::new(ip, port), timeout)
+}
+
+impl Sim868 {
+    pub fn ntp_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<String>> {
+        if line.starts_with(AT_CNTPCID) {
+            Some(self.cntpcid(line))
+        } else if line.starts_with(AT_CNTP) {
+            Some(self.cntp(line, tx))
+        } else {
+            None
+        }
+    }
+
+    /// What is wrong with the `ntp.*` keys, for the log
+    pub fn ntp_config_problems(&self) -> Vec<String> {
+        match self.config.get("ntp.result") {
+            Some(code) if scripted_result(Some(code)).is_none() => vec![format!(
+                "ntp.result {} is not 1 or 61 to 65, AT+CNTP queries the server",
+                code
+            )],
+            _ => vec![],
+        }
+    }
+
+    /// `AT+CNTPCID=<cid>`, the bearer profile the synchronization runs on
+    fn cntpcid(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CNTPCID.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!(format!("+CNTPCID: (1-{})", BEARER_PROFILES)), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![at!(format!("+CNTPCID: {}", self.ntp.cid)), at!(OK)];
+        }
+        match params(line, AT_CNTPCID)
+            .first()
+            .and_then(|cid| cid.parse::<usize>().ok())
+        {
+            Some(cid) if (1..=BEARER_PROFILES).contains(&cid) => {
+                self.ntp.cid = cid;
+                vec![at!(OK)]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    /// `AT+CNTP="<server>"[,<tz>[,<cid>]]` configures, `AT+CNTP` synchronizes the RTC and
+    /// reports `+CNTP: <code>` when done
+    fn cntp(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let rest = line[AT_CNTP.len()..].trim();
+        if rest.starts_with("=?") {
+            return vec![
+                at!(format!(
+                    "+CNTP: {},({}-{})",
+                    NTP_SERVER_MAX,
+                    ZONE_RANGE.start(),
+                    ZONE_RANGE.end()
+                )),
+                at!(OK),
+            ];
+        }
+        if rest.starts_with('?') {
+            return vec![
+                at!(format!("+CNTP: \"{}\",{}", self.ntp.server, self.ntp.zone)),
+                at!(OK),
+            ];
+        }
+        if rest.is_empty() {
+            return self.synchronize_rtc(tx);
+        }
+        let args = params(line, AT_CNTP);
+        let server = args.first().filter(|s| s.len() <= NTP_SERVER_MAX);
+        let zone = match args.get(1) {
+            Some(zone) => zone.parse::<i32>().ok().filter(|z| ZONE_RANGE.contains(z)),
+            None => Some(self.ntp.zone),
+        };
+        let cid = match args.get(2) {
+            Some(cid) => cid
+                .parse::<usize>()
+                .ok()
+                .filter(|c| (1..=BEARER_PROFILES).contains(c)),
+            None => Some(self.ntp.cid),
+        };
+        let (Some(server), Some(zone), Some(cid)) = (server, zone, cid) else {
+            return vec![at!(ERROR)];
+        };
+        self.ntp.server = server.to_owned();
+        self.ntp.zone = zone;
+        self.ntp.cid = cid;
+        vec![at!(OK)]
+    }
+
+    /// Queries the server on its own thread, nothing goes out without an open bearer
+    fn synchronize_rtc(&mut self, tx: Sender<Vec<u8>>) -> Vec<String> {
+        if self.ntp.server.is_empty() {
+            return vec![at!(ERROR)];
+        }
+        let bearer_open = self.open_bearer(self.ntp.cid).is_some();
+        let config = self.config.clone();
+        let server = self.ntp.server.clone();
+        let zone = self.ntp.zone;
+        let rtc = self.rtc.clone();
+        let power_cycle = self.power_cycle.clone();
+        let cycle = power_cycle.load(Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let result = if bearer_open {
+                synchronize(&config, &server)
+            } else {
+                Err(NTP_NETWORK_ERROR)
+            };
+            if scripted_result(config.get("ntp.result")).is_some() || !bearer_open {
+                std::thread::sleep(Duration::from_millis(config.get_or("ntp.delay_ms", 1000)));
+            }
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                return;
+            }
+            let code = match result {
+                Ok(utc) => {
+                    rtc.lock().unwrap().set(utc, zone);
+                    NTP_SUCCESS
+                }
+                Err(code) => code,
+            };
+            let _ = tx.send(at!(format!("+CNTP: {}", code)).into());
+        });
+        vec![at!(OK)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 4 server reply at stratum 2 with the given transmit timestamp
+    fn reply(seconds: u32, fraction: u32) -> [u8; 48] {
+        let mut reply = [0u8; 48];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply[40..44].copy_from_slice(&seconds.to_be_bytes());
+        reply[44..48].copy_from_slice(&fraction.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn scripted_results_are_cntp_codes() {
+        let scripted = |value| scripted_result(Some(value));
+        assert_eq!(scripted_result(None), None);
+        assert_eq!(scripted("1"), Some(NTP_SUCCESS));
+        assert_eq!(scripted("61"), Some(NTP_NETWORK_ERROR));
+        assert_eq!(scripted("65"), Some(NTP_TIMEOUT));
+        for typo in ["0", "6", "60", "66", "x"] {
+            assert_eq!(scripted(typo), None, "{}", typo);
+        }
+    }
+
+    #[test]
+    fn transmit_time_in_unix_seconds() {
+        // 2024-01-01 00:00:00 UTC
+        let seconds = 1_704_067_200 + NTP_UNIX_OFFSET as u32;
+        assert_eq!(transmit_time(&reply(seconds, 0)), Ok(1_704_067_200.0));
+        assert_eq!(
+            transmit_time(&reply(seconds, 0x8000_0000)),
+            Ok(1_704_067_200.5)
+        );
+    }
+
+    #[test]
+    fn transmit_time_rejects_what_is_not_a_server_reply() {
+        let good = reply(3_913_056_000, 0);
+        assert!(transmit_time(&good).is_ok());
+        assert_eq!(transmit_time(&good[..47]), Err(NTP_RESPONSE_ERROR));
+        let mut client = good;
+        client[0] = 0x23;
+        assert_eq!(transmit_time(&client), Err(NTP_RESPONSE_ERROR));
+        let mut kiss_of_death = good;
+        kiss_of_death[1] = 0;
+        assert_eq!(transmit_time(&kiss_of_death), Err(NTP_RESPONSE_ERROR));
+        assert_eq!(transmit_time(&reply(0, 0)), Err(NTP_RESPONSE_ERROR));
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    sync::{atomic::Ordering, mpsc::Sender},
+    time::Duration,
+};
+
+use crate::sim868::{
+    bearer::{self, BEARER_CLOSED},
+    ftp::Ftp,
+    http::Http,
+    ntp::Ntp,
+    params,
+    sleep::Sleep,
+    GnssConfig, Sim868, ERROR, OK,
+};
+
+const AT_CPOWD: &str = "AT+CPOWD";
+const AT_CFUN: &str = "AT+CFUN";
+
+/// Full functionality, the only level with the radio on
+pub const FUN_FULL: u8 = 1;
+/// Minimum functionality, radio and SIM off
+pub const FUN_MINIMUM: u8 = 0;
+/// Flight mode, radio off
+pub const FUN_FLIGHT: u8 = 4;
+
+/// Milliseconds after power-up each start-up URC comes
+const BOOT_RDY_MS: u64 = 2500;
+const BOOT_CFUN_MS: u64 = 2700;
+const BOOT_CPIN_MS: u64 = 3000;
+const BOOT_CALL_READY_MS: u64 = 8000;
+const BOOT_SMS_READY_MS: u64 = 10000;
+/// Milliseconds after the radio is turned back on each of its URCs comes
+const RADIO_CPIN_MS: u64 = 300;
+const RADIO_CALL_READY_MS: u64 = 2000;
+const RADIO_SMS_READY_MS: u64 = 3000;
+
+impl Sim868 {
+    pub fn power_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<String>> {
+        if line.starts_with(AT_CPOWD) {
+            Some(self.cpowd(line, tx))
+        } else if line.starts_with(AT_CFUN) {
+            Some(self.cfun(line, tx))
+        } else {
+            None
+        }
+    }
+
+    pub fn fun_mode(&self) -> u8 {
+        self.configs.fun_mode.unwrap_or(FUN_FULL)
+    }
+
+    /// Only full functionality has the radio on, the network commands fail at other levels
+    pub fn radio_on(&self) -> bool {
+        self.fun_mode() == FUN_FULL
+    }
+
+    /// Turns the module on or off like the PWRKEY does. Booting prints the start-up URCs,
+    /// powering down drops everything the module was doing.
+    pub fn set_power(&mut self, on: bool, tx: Sender<Vec<u8>>) {
+        if on == self.power {
+            return;
+        }
+        if on {
+            self.boot(FUN_FULL, tx);
+        } else {
+            self.shutdown();
+        }
+    }
+
+    /// Threads of an earlier power or radio state see the count move on and stop
+    fn next_cycle(&self) -> u64 {
+        self.power_cycle.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn boot(&mut self, fun: u8, tx: Sender<Vec<u8>>) {
+        self.power = true;
+        self.configs.fun_mode = Some(fun);
+        self.sleep = Sleep::from_config(&self.config);
+        self.gnss.lock().unwrap().notify(GnssConfig::Status(true));
+        self.start_rtc();
+        let profile = self.stored_profile();
+        self.apply_profile(&profile, true);
+        let baud = profile.baudrate;
+        // an auto-bauding module has no speed to print RDY at
+        let mut urcs = vec![];
+        if baud != 0 {
+            urcs.push((BOOT_RDY_MS, "RDY".to_owned()));
+        }
+        urcs.push((BOOT_CFUN_MS, format!("+CFUN: {}", fun)));
+        if fun != FUN_MINIMUM {
+            urcs.push((BOOT_CPIN_MS, "+CPIN: READY".to_owned()));
+        }
+        if fun == FUN_FULL {
+            urcs.push((BOOT_CALL_READY_MS, "Call Ready".to_owned()));
+            urcs.push((BOOT_SMS_READY_MS, "SMS Ready".to_owned()));
+        }
+        self.start_up(urcs, tx);
+    }
+
+    /// Sends the URCs at their time from now, registration comes back with `Call Ready` and the
+    /// network time follows it
+    fn start_up(&self, urcs: Vec<(u64, String)>, tx: Sender<Vec<u8>>) {
+        let cycle = self.next_cycle();
+        let power_cycle = self.power_cycle.clone();
+        let reg_status = self.reg_status.clone();
+        let registration = self.registration.clone();
+        let scale = self.config.get_or("power.boot_scale", 1.0f64).max(0.0);
+        self.network_time_thread(cycle, tx.clone());
+        std::thread::spawn(move || {
+            let mut elapsed = 0;
+            for (at, urc) in urcs {
+                std::thread::sleep(Duration::from_secs_f64(
+                    (at - elapsed) as f64 * scale / 1000.0,
+                ));
+                elapsed = at;
+                if power_cycle.load(Ordering::SeqCst) != cycle {
+                    return;
+                }
+                if urc == "Call Ready" {
+                    // a registration set while booting is the current one
+                    let mut reg_status = reg_status.lock().unwrap();
+                    if *reg_status == 0 {
+                        *reg_status = registration.load(Ordering::SeqCst);
+                    }
+                }
+                if tx.send(at!(urc).into()).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Off the network: no registration, no sockets and no bearer. The registration is kept
+    /// to come back once the radio is on again.
+    pub fn detach(&mut self) {
+        self.next_cycle();
+        let mut reg_status = self.reg_status.lock().unwrap();
+        if *reg_status != 0 {
+            self.registration.store(*reg_status, Ordering::SeqCst);
+        }
+        *reg_status = 0;
+        drop(reg_status);
+        self.tcpip.shut();
+        for bearer in self.bearers.iter_mut() {
+            bearer.status = BEARER_CLOSED;
+            bearer.ip = "0.0.0.0".to_owned();
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.detach();
+        self.power = false;
+        self.bearers = vec![bearer::BearerProfile::new(); bearer::BEARER_PROFILES];
+        self.http = Http::new();
+        self.ftp = Ftp::new();
+        self.ntp = Ntp::new();
+        self.pending_input = None;
+        self.sleep = Sleep::from_config(&self.config);
+        self.configs.fun_mode = None;
+        let mut gnss = self.gnss.lock().unwrap();
+        gnss.set_power(false);
+        gnss.notify(GnssConfig::Status(false));
+    }
+
+    /// `AT+CPOWD=1` says goodbye before powering off, `AT+CPOWD=0` powers off at once
+    fn cpowd(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let answer = match params(line, AT_CPOWD).first().map(|p| p.as_str()) {
+            Some("1") => vec![at!("NORMAL POWER DOWN")],
+            Some("0") => vec![],
+            _ => return vec![at!(ERROR)],
+        };
+        self.set_power(false, tx);
+        answer
+    }
+
+    /// `AT+CFUN=<fun>[,<rst>]`, levels 0 and 4 turn the radio off, `<rst>` 1 reboots into the level
+    fn cfun(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        let rest = &line[AT_CFUN.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+CFUN: (0,1,4),(0,1)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![at!(format!("+CFUN: {}", self.fun_mode())), at!(OK)];
+        }
+        let args = params(line, AT_CFUN);
+        let fun = args
+            .first()
+            .and_then(|f| f.parse::<u8>().ok())
+            .filter(|f| matches!(*f, FUN_MINIMUM | FUN_FULL | FUN_FLIGHT));
+        let reset = match args.get(1).map(|r| r.as_str()) {
+            None | Some("0") => false,
+            Some("1") => true,
+            _ => return vec![at!(ERROR)],
+        };
+        let Some(fun) = fun else {
+            return vec![at!(ERROR)];
+        };
+        if reset {
+            self.shutdown();
+            self.boot(fun, tx);
+            return vec![at!(OK)];
+        }
+        let previous = self.fun_mode();
+        self.configs.fun_mode = Some(fun);
+        let mut answer = vec![];
+        if previous == FUN_FULL && fun != FUN_FULL {
+            self.detach();
+        }
+        if previous != FUN_MINIMUM && fun == FUN_MINIMUM {
+            answer.push(at!("+CPIN: NOT READY"));
+        }
+        answer.push(at!(OK));
+        if previous != FUN_FULL && fun == FUN_FULL {
+            let mut urcs = vec![];
+            if previous == FUN_MINIMUM {
+                urcs.push((RADIO_CPIN_MS, "+CPIN: READY".to_owned()));
+            }
+            urcs.push((RADIO_CALL_READY_MS, "Call Ready".to_owned()));
+            urcs.push((RADIO_SMS_READY_MS, "SMS Ready".to_owned()));
+            self.start_up(urcs, tx);
+        }
+        answer
+    }
+}
@@ -0,0 +1,271 @@
+use std::{fs, sync::atomic::Ordering};
+
+use crate::{
+    config::Config,
+    sim868::{ipr_valid, params, start_baud, Sim868, ERROR, OK},
+};
+
+const AT_AND_W: &str = "AT&W";
+const AT_AND_F: &str = "AT&F";
+const AT_AND_V: &str = "AT&V";
+const ATZ: &str = "ATZ";
+const AT_CLIP: &str = "AT+CLIP";
+const AT_CNMI: &str = "AT+CNMI";
+
+const DEFAULT_PROFILE_PATH: &str = "sim868.profile";
+
+/// Values `AT+CNMI=?` advertises for `<mode>,<mt>,<bm>,<ds>,<bfr>`
+const CNMI_VALUES: [&[u8]; 5] = [&[0, 1, 2, 3], &[0, 1, 2, 3], &[0, 2], &[0, 1, 2], &[0, 1]];
+
+fn cnmi_valid(cnmi: &[u8]) -> bool {
+    cnmi.len() <= CNMI_VALUES.len()
+        && cnmi
+            .iter()
+            .zip(CNMI_VALUES)
+            .all(|(value, values)| values.contains(value))
+}
+
+/// Settings `AT&W` keeps in the module's flash and every power-up starts from
+#[derive(Clone, PartialEq)]
+pub struct Profile {
+    pub echo: bool,
+    pub cmee: u8,
+    pub baudrate: usize,
+    pub dtr_mode: u8,
+    pub dcd_mode: u8,
+    pub output_flow: u8,
+    pub input_flow: u8,
+    pub ri_on_urc: bool,
+    pub clip: u8,
+    /// `<mode>,<mt>,<bm>,<ds>,<bfr>`
+    pub cnmi: [u8; 5],
+    pub clts: bool,
+}
+
+impl Profile {
+    /// What the module leaves the factory with, the speed is the one it starts at
+    pub fn factory(config: &Config) -> Profile {
+        Profile {
+            echo: false,
+            cmee: 0,
+            baudrate: start_baud(config),
+            dtr_mode: 1,
+            dcd_mode: 1,
+            output_flow: 0,
+            input_flow: 0,
+            ri_on_urc: false,
+            clip: 0,
+            cnmi: [2, 1, 0, 0, 0],
+            clts: false,
+        }
+    }
+
+    /// The profile in `path`, a setting it lacks keeps its factory value
+    fn load(path: &str, config: &Config) -> Option<Profile> {
+        if fs::metadata(path).is_err() {
+            return None;
+        }
+        let saved = Config::load(path);
+        let factory = Profile::factory(config);
+        let cnmi: Vec<u8> = saved
+            .get("cnmi")
+            .map(|c| c.split(',').filter_map(|v| v.trim().parse().ok()).collect())
+            .unwrap_or_default();
+        Some(Profile {
+            echo: saved.get_or("echo", factory.echo),
+            cmee: saved.get_or("cmee", factory.cmee),
+            // a speed the port cannot take would leave the host unable to talk to the module
+            baudrate: Some(saved.get_or("ipr", factory.baudrate))
+                .filter(|b| ipr_valid(*b))
+                .unwrap_or(factory.baudrate),
+            dtr_mode: saved.get_or("dtr_mode", factory.dtr_mode),
+            dcd_mode: saved.get_or("dcd_mode", factory.dcd_mode),
+            output_flow: saved.get_or("ifc_dce_by_dte", factory.output_flow),
+            input_flow: saved.get_or("ifc_dte_by_dce", factory.input_flow),
+            ri_on_urc: saved.get_or("cfgri", factory.ri_on_urc),
+            clip: saved.get_or("clip", factory.clip),
+            cnmi: cnmi
+                .try_into()
+                .ok()
+                .filter(|c: &[u8; 5]| cnmi_valid(c))
+                .unwrap_or(factory.cnmi),
+            clts: saved.get_or("clts", factory.clts),
+        })
+    }
+
+    fn save(&self, path: &str) -> std::io::Result<()> {
+        let cnmi: Vec<String> = self.cnmi.iter().map(|v| v.to_string()).collect();
+        let content = format!(
+            "# user profile written by AT&W\n\
+             echo = {}\ncmee = {}\nipr = {}\ndtr_mode = {}\ndcd_mode = {}\n\
+             ifc_dce_by_dte = {}\nifc_dte_by_dce = {}\ncfgri = {}\nclip = {}\ncnmi = {}\nclts = {}\n",
+            self.echo,
+            self.cmee,
+            self.baudrate,
+            self.dtr_mode,
+            self.dcd_mode,
+            self.output_flow,
+            self.input_flow,
+            self.ri_on_urc,
+            self.clip,
+            cnmi.join(","),
+            self.clts
+        );
+        fs::write(path, content)
+    }
+
+    /// The settings the way AT&V lists them
+    fn describe(&self) -> String {
+        let cnmi: Vec<String> = self.cnmi.iter().map(|v| v.to_string()).collect();
+        format!(
+            "E{} &C{} &D{} +IPR: {} +IFC: {},{} +CMEE: {} +CLIP: {} +CNMI: {} +CFGRI: {} +CLTS: {}",
+            self.echo as u8,
+            self.dcd_mode,
+            self.dtr_mode,
+            self.baudrate,
+            self.output_flow,
+            self.input_flow,
+            self.cmee,
+            self.clip,
+            cnmi.join(","),
+            self.ri_on_urc as u8,
+            self.clts as u8
+        )
+    }
+}
+
+impl Sim868 {
+    pub fn profile_command(&mut self, line: &str) -> Option<Vec<String>> {
+        if line.starts_with(AT_AND_W) {
+            Some(self.and_w(line))
+        } else if line.starts_with(AT_AND_F) {
+            Some(self.restore_profile(Profile::factory(&self.config)))
+        } else if line.starts_with(ATZ) {
+            Some(self.restore_profile(self.stored_profile()))
+        } else if line.starts_with(AT_AND_V) {
+            Some(vec![
+                at!(format!("ACTIVE PROFILE:\r\n{}", self.profile().describe())),
+                at!(format!(
+                    "STORED PROFILE:\r\n{}",
+                    self.stored_profile().describe()
+                )),
+                at!(OK),
+            ])
+        } else if line.starts_with(AT_CLIP) {
+            Some(self.clip(line))
+        } else if line.starts_with(AT_CNMI) {
+            Some(self.cnmi(line))
+        } else {
+            None
+        }
+    }
+
+    fn profile_path(&self) -> &str {
+        self.config
+            .get("profile.file")
+            .unwrap_or(DEFAULT_PROFILE_PATH)
+    }
+
+    /// What `AT&W` saved last, the factory profile before it ever ran
+    pub fn stored_profile(&self) -> Profile {
+        Profile::load(self.profile_path(), &self.config)
+            .unwrap_or_else(|| Profile::factory(&self.config))
+    }
+
+    /// The settings in effect now
+    pub fn profile(&self) -> Profile {
+        Profile {
+            echo: self.configs.echo,
+            cmee: self.configs.cmee,
+            baudrate: self.configs.baudrate,
+            dtr_mode: self.modem.dtr_mode,
+            dcd_mode: self.modem.dcd_mode,
+            output_flow: self.lines.output_flow.load(Ordering::Relaxed),
+            input_flow: self.lines.input_flow.load(Ordering::Relaxed),
+            ri_on_urc: self.modem.ri_on_urc.load(Ordering::Relaxed),
+            clip: self.configs.clip,
+            cnmi: self.configs.cnmi,
+            clts: self.rtc.lock().unwrap().clts,
+        }
+    }
+
+    /// Puts the settings of `profile` in effect, the UART speed only changes when `with_baudrate`
+    pub fn apply_profile(&mut self, profile: &Profile, with_baudrate: bool) {
+        self.configs.echo = profile.echo;
+        self.configs.cmee = profile.cmee;
+        self.modem.dtr_mode = profile.dtr_mode;
+        self.modem.dcd_mode = profile.dcd_mode;
+        self.lines
+            .output_flow
+            .store(profile.output_flow, Ordering::Relaxed);
+        self.lines
+            .input_flow
+            .store(profile.input_flow, Ordering::Relaxed);
+        self.modem
+            .ri_on_urc
+            .store(profile.ri_on_urc, Ordering::Relaxed);
+        self.configs.clip = profile.clip;
+        self.configs.cnmi = profile.cnmi;
+        self.rtc.lock().unwrap().clts = profile.clts;
+        if with_baudrate {
+            self.set_baudrate(profile.baudrate);
+        }
+    }
+
+    /// `AT&F` and `ATZ` answer at the current speed and keep it, the host would lose the
+    /// module otherwise
+    fn restore_profile(&mut self, profile: Profile) -> Vec<String> {
+        self.apply_profile(&profile, false);
+        vec![at!(OK)]
+    }
+
+    /// `AT&W[0]` writes the active settings to the profile file
+    fn and_w(&mut self, line: &str) -> Vec<String> {
+        if !matches!(line[AT_AND_W.len()..].trim(), "" | "0") {
+            return vec![at!(ERROR)];
+        }
+        match self.profile().save(self.profile_path()) {
+            Ok(()) => vec![at!(OK)],
+            Err(_) => vec![at!(ERROR)],
+        }
+    }
+
+    /// `AT+CLIP=<n>` only remembers the setting, no call ever rings
+    fn clip(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CLIP.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+CLIP: (0,1)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            // the network always provides the caller's number
+            return vec![at!(format!("+CLIP: {},1", self.configs.clip)), at!(OK)];
+        }
+        match params(line, AT_CLIP).first().map(|n| n.as_str()) {
+            Some("0") => self.configs.clip = 0,
+            Some("1") => self.configs.clip = 1,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    /// `AT+CNMI=<mode>[,<mt>[,<bm>[,<ds>[,<bfr>]]]]` only remembers the setting
+    fn cnmi(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CNMI.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+CNMI: (0-3),(0-3),(0,2),(0-2),(0,1)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            let cnmi: Vec<String> = self.configs.cnmi.iter().map(|v| v.to_string()).collect();
+            return vec![at!(format!("+CNMI: {}", cnmi.join(","))), at!(OK)];
+        }
+        let args: Option<Vec<u8>> = params(line, AT_CNMI)
+            .iter()
+            .map(|a| a.parse().ok())
+            .collect();
+        let Some(args) = args.filter(|a| !a.is_empty() && cnmi_valid(a)) else {
+            return vec![at!(ERROR)];
+        };
+        self.configs.cnmi[..args.len()].copy_from_slice(&args);
+        vec![at!(OK)]
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    sync::{atomic::Ordering, mpsc::Sender, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    sim868::{params, Sim868, ERROR, OK},
+    utils::time::{self, DateTime},
+};
+
+const AT_CCLK: &str = "AT+CCLK";
+const AT_CLTS: &str = "AT+CLTS";
+
+/// Where the RTC of a module that never synced stands, `04/01/01,00:00:00+00`
+const FACTORY_TIME: f64 = 1_072_915_200.0;
+
+/// Milliseconds after registration the network sends its time
+const NETWORK_TIME_MS: u64 = 2000;
+
+/// Quarter hours the zone of `AT+CCLK` may be off UTC
+pub const ZONE_RANGE: std::ops::RangeInclusive<i32> = -47..=48;
+
+/// Real time clock, it runs on the backup battery and keeps going while the module is off
+pub struct Rtc {
+    /// Seconds the RTC is ahead of the host, unset until the first power-up
+    offset: Option<f64>,
+    /// Zone in quarter hours
+    zone: i32,
+    /// `AT+CLTS`, the RTC follows the network time
+    pub clts: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            offset: None,
+            zone: 0,
+            clts: false,
+        }
+    }
+
+    /// UTC the RTC shows now
+    fn now(&self) -> f64 {
+        time::now() + self.offset.unwrap_or(0.0)
+    }
+
+    pub fn set(&mut self, utc: f64, zone: i32) {
+        self.offset = Some(utc - time::now());
+        self.zone = zone;
+    }
+}
+
+/// The time the network hands out after registration, right or as wrong as configured
+#[derive(Clone, Copy)]
+pub struct NetworkTime {
+    error: f64,
+    zone: i32,
+}
+
+impl NetworkTime {
+    /// With `AT+CLTS=1` the RTC takes the network time and the URCs report it
+    pub fn deliver(self, rtc: &Mutex<Rtc>) -> Vec<String> {
+        let mut rtc = rtc.lock().unwrap();
+        if !rtc.clts {
+            return vec![];
+        }
+        let utc = time::now() + self.error;
+        rtc.set(utc, self.zone);
+        let t = DateTime::from_unix(utc);
+        vec![
+            at!(format!("+CTZV: {:+03},0", self.zone)),
+            at!(format!(
+                "*PSUTTZ: {},{},{},{},{},{},\"{:+03}\",0",
+                t.year, t.month, t.day, t.hour, t.minute, t.second, self.zone
+            )),
+            at!("DST: 0"),
+        ]
+    }
+}
+
+/// `yy/MM/dd,hh:mm:ss±zz` in local time, the UTC and the zone it stands for
+fn parse_cclk(text: &str) -> Option<(f64, i32)> {
+    let (date, clock) = text.split_once(',')?;
+    let zone_at = clock.rfind(['+', '-'])?;
+    let (clock, zone) = clock.split_at(zone_at);
+    let date: Vec<u32> = date
+        .split('/')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let clock: Vec<u32> = clock
+        .split(':')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let zone: i32 = zone.parse().ok()?;
+    let (&[yy, month, day], &[hour, minute, second]) = (&date[..], &clock[..]) else {
+        return None;
+    };
+    if yy > 99 || hour > 23 || minute > 59 || second > 59 || !ZONE_RANGE.contains(&zone) {
+        return None;
+    }
+    let local = DateTime {
+        year: 2000 + i64::from(yy),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        millis: 0,
+    };
+    // a day the month does not have comes back as another date
+    if !(1..=12).contains(&month) || DateTime::from_unix(local.to_unix()) != local {
+        return None;
+    }
+    Some((local.to_unix() - f64::from(zone) * 900.0, zone))
+}
+
+impl Sim868 {
+    pub fn rtc_command(&mut self, line: &str) -> Option<Vec<String>> {
+        if line.starts_with(AT_CCLK) {
+            Some(self.cclk(line))
+        } else if line.starts_with(AT_CLTS) {
+            Some(self.clts(line))
+        } else {
+            None
+        }
+    }
+
+    /// Starts the RTC at `rtc.initial` on the first power-up, later ones find it running
+    pub fn start_rtc(&mut self) {
+        let mut rtc = self.rtc.lock().unwrap();
+        if rtc.offset.is_some() {
+            return;
+        }
+        let initial = match self.config.get("rtc.initial").unwrap_or("factory") {
+            "factory" => FACTORY_TIME,
+            "host" => time::now(),
+            text => DateTime::parse_iso8601(text).map_or(FACTORY_TIME, |t| t.to_unix()),
+        };
+        rtc.set(initial, 0);
+    }
+
+    /// What the network sends after registration, nothing with `rtc.network = none`
+    pub fn network_time(&self) -> Option<NetworkTime> {
+        if self.config.get("rtc.network") == Some("none") {
+            return None;
+        }
+        Some(NetworkTime {
+            error: self.config.get_or("rtc.network_error_s", 0.0),
+            zone: self
+                .config
+                .get_or("rtc.network_zone", 0)
+                .clamp(*ZONE_RANGE.start(), *ZONE_RANGE.end()),
+        })
+    }
+
+    /// Sends the network time once the module registers during power cycle `cycle`
+    pub fn network_time_thread(&self, cycle: u64, tx: Sender<Vec<u8>>) {
+        let Some(network_time) = self.network_time() else {
+            return;
+        };
+        let power_cycle = self.power_cycle.clone();
+        let reg_status = self.reg_status.clone();
+        let rtc = self.rtc.clone();
+        let scale = self.config.get_or("power.boot_scale", 1.0f64).max(0.0);
+        std::thread::spawn(move || {
+            while !matches!(*reg_status.lock().unwrap(), 1 | 5) {
+                std::thread::sleep(Duration::from_millis(100));
+                if power_cycle.load(Ordering::SeqCst) != cycle {
+                    return;
+                }
+            }
+            std::thread::sleep(Duration::from_secs_f64(
+                NETWORK_TIME_MS as f64 * scale / 1000.0,
+            ));
+            if power_cycle.load(Ordering::SeqCst) != cycle {
+                return;
+            }
+            for urc in network_time.deliver(&rtc) {
+                if tx.send(urc.into()).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// `AT+CCLK?` reads the local time, `AT+CCLK="yy/MM/dd,hh:mm:ss±zz"` sets it
+    fn cclk(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CCLK.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!(OK)];
+        }
+        let mut rtc = self.rtc.lock().unwrap();
+        if rest.starts_with('?') {
+            let t = DateTime::from_unix(rtc.now() + f64::from(rtc.zone) * 900.0);
+            return vec![
+                at!(format!(
+                    "+CCLK: \"{:02}/{:02}/{:02},{:02}:{:02}:{:02}{:+03}\"",
+                    t.year % 100,
+                    t.month,
+                    t.day,
+                    t.hour,
+                    t.minute,
+                    t.second,
+                    rtc.zone
+                )),
+                at!(OK),
+            ];
+        }
+        match params(line, AT_CCLK)
+            .first()
+            .and_then(|time| parse_cclk(time.trim_matches('"')))
+        {
+            Some((utc, zone)) => {
+                rtc.set(utc, zone);
+                vec![at!(OK)]
+            }
+            None => vec![at!(ERROR)],
+        }
+    }
+
+    /// `AT+CLTS=<mode>`, whether the network time updates the RTC
+    fn clts(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CLTS.len()..];
+        let mut rtc = self.rtc.lock().unwrap();
+        if rest.starts_with("=?") {
+            return vec![at!("+CLTS: (0,1)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![at!(format!("+CLTS: {}", rtc.clts as u8)), at!(OK)];
+        }
+        match params(line, AT_CLTS).first().map(|n| n.as_str()) {
+            Some("0") => rtc.clts = false,
+            Some("1") => rtc.clts = true,
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cclk_in_utc() {
+        assert_eq!(
+            parse_cclk("24/02/29,12:00:00+00"),
+            Some((1_709_208_000.0, 0))
+        );
+    }
+
+    #[test]
+    fn cclk_zones_are_quarter_hours() {
+        // local midnight three hours ahead of UTC is 21:00 the day before
+        assert_eq!(
+            parse_cclk("24/01/01,00:00:00+12"),
+            Some((1_704_056_400.0, 12))
+        );
+        assert_eq!(
+            parse_cclk("24/01/01,00:00:00-20"),
+            Some((1_704_085_200.0, -20))
+        );
+        assert_eq!(
+            parse_cclk("24/01/01,00:00:00-47"),
+            Some((1_704_067_200.0 + 47.0 * 900.0, -47))
+        );
+    }
+
+    #[test]
+    fn cclk_rejects_bad_dates() {
+        for text in [
+            "23/02/29,12:00:00+00",
+            "24/04/31,12:00:00+00",
+            "24/13/01,12:00:00+00",
+            "24/00/10,12:00:00+00",
+            "24/01/00,12:00:00+00",
+            "124/01/01,12:00:00+00",
+            "24/01/01,24:00:00+00",
+            "24/01/01,12:60:00+00",
+            "24/01/01,12:00:60+00",
+            "24/01,12:00:00+00",
+            "24/01/01,12:00+00",
+            "aa/01/01,12:00:00+00",
+        ] {
+            assert_eq!(parse_cclk(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn cclk_rejects_bad_zones() {
+        for text in [
+            "24/01/01,12:00:00",
+            "24/01/01,12:00:00+49",
+            "24/01/01,12:00:00-48",
+            "24/01/01,12:00:00+x",
+            "24/01/01 12:00:00+00",
+        ] {
+            assert_eq!(parse_cclk(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::Config,
+    sim868::{params, Sim868, ERROR, OK},
+};
+
+const AT_CSCLK: &str = "AT+CSCLK";
+
+/// Sleep mode 1, the module sleeps while the host DTR is released
+pub const SLEEP_DTR: u8 = 1;
+/// Sleep mode 2, the module sleeps once the UART is idle and wakes on incoming data
+pub const SLEEP_IDLE: u8 = 2;
+
+/// Slow clock set by `AT+CSCLK=<n>`
+pub struct Sleep {
+    pub mode: u8,
+    /// last time something came in on the UART
+    pub last_activity: Instant,
+    /// `sleep.idle_ms`, also how long a URC keeps the module awake
+    idle: Duration,
+    /// `sleep.lost_chars`
+    lost_chars: usize,
+}
+
+impl Sleep {
+    pub fn from_config(config: &Config) -> Sleep {
+        Sleep {
+            mode: 0,
+            last_activity: Instant::now(),
+            idle: Duration::from_millis(config.get_or("sleep.idle_ms", 5000)),
+            lost_chars: config.get_or("sleep.lost_chars", 2),
+        }
+    }
+}
+
+impl Sim868 {
+    pub fn sleep_command(&mut self, line: &str) -> Option<Vec<String>> {
+        if line.starts_with(AT_CSCLK) {
+            Some(self.csclk(line))
+        } else {
+            None
+        }
+    }
+
+    /// A URC or data from a link wakes the module in either mode, for as long as the UART idle
+    /// time of mode 2
+    pub fn asleep(&self) -> bool {
+        let idle = self.sleep.idle;
+        if self.modem.last_urc.lock().unwrap().elapsed() < idle {
+            return false;
+        }
+        match self.sleep.mode {
+            SLEEP_DTR => !self.lines.dtr.load(Ordering::Relaxed),
+            SLEEP_IDLE => self.sleep.last_activity.elapsed() >= idle,
+            _ => false,
+        }
+    }
+
+    /// What an incoming line leaves for the AT parser. Asleep in mode 1 the UART is off and the
+    /// line is lost; in mode 2 it wakes the module, which misses its first characters.
+    pub fn wake(&mut self, line: &str) -> Option<String> {
+        let asleep = self.asleep();
+        self.sleep.last_activity = Instant::now();
+        if !asleep {
+            return Some(line.to_owned());
+        }
+        if self.sleep.mode == SLEEP_DTR {
+            return None;
+        }
+        line.get(self.sleep.lost_chars..)
+            .filter(|rest| rest.starts_with("AT"))
+            .map(|rest| rest.to_owned())
+    }
+
+    fn csclk(&mut self, line: &str) -> Vec<String> {
+        let rest = &line[AT_CSCLK.len()..];
+        if rest.starts_with("=?") {
+            return vec![at!("+CSCLK: (0,1,2)"), at!(OK)];
+        }
+        if rest.starts_with('?') {
+            return vec![at!(format!("+CSCLK: {}", self.sleep.mode)), at!(OK)];
+        }
+        match params(line, AT_CSCLK)
+            .first()
+            .and_then(|m| m.parse::<u8>().ok())
+        {
+            Some(mode @ 0..=2) => {
+                self.sleep.mode = mode;
+                vec![at!(OK)]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    sim868::{params, PendingInput, Sim868, ERROR, OK},
+    utils::serial::PortControl,
+};
+
+const AT_CIPMUX: &str = "AT+CIPMUX";
+const AT_CIPHEAD: &str = "AT+CIPHEAD";
+const AT_CIPSERVER: &str = "AT+CIPSERVER";
+const AT_CIPSEND: &str = "AT+CIPSEND";
+const AT_CIPCLOSE: &str = "AT+CIPCLOSE";
+
+/// links the module can hold at once in multi connection mode
+pub const MAX_LINKS: usize = 6;
+
+type Links = Arc<Mutex<Vec<Option<TcpStream>>>>;
+
+/// State of the module's TCP/IP application, the sockets are real ones opened on the PC.
+pub struct TcpIp {
+    /// AT+CIPMUX and AT+CIPHEAD, shared with the link threads so a change reaches open links
+    pub mux: Arc<AtomicBool>,
+    pub head: Arc<AtomicBool>,
+    pub server_port: Option<u16>,
+    server_running: Option<Arc<AtomicBool>>,
+    pub links: Links,
+}
+
+impl TcpIp {
+    pub fn new() -> TcpIp {
+        TcpIp {
+            mux: Arc::new(AtomicBool::new(false)),
+            head: Arc::new(AtomicBool::new(false)),
+            server_port: None,
+            server_running: None,
+            links: Arc::new(Mutex::new((0..MAX_LINKS).map(|_| None).collect())),
+        }
+    }
+
+    /// Closes every link and stops the server, what losing the network does to the stack
+    pub fn shut(&mut self) {
+        for link in self.links.lock().unwrap().iter_mut() {
+            if let Some(stream) = link.take() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        if let Some(running) = self.server_running.take() {
+            running.store(false, Ordering::Relaxed);
+        }
+        self.server_port = None;
+    }
+
+    /// Whether any link is up, what DCD follows under AT&C1
+    pub fn any_connected(&self) -> bool {
+        self.links.lock().unwrap().iter().any(|l| l.is_some())
+    }
+
+    fn mux(&self) -> bool {
+        self.mux.load(Ordering::Relaxed)
+    }
+
+    fn is_connected(&self, link: usize) -> bool {
+        self.links
+            .lock()
+            .unwrap()
+            .get(link)
+            .is_some_and(|l| l.is_some())
+    }
+}
+
+/// `<n>, ` prefix every link related message carries in multi connection mode
+fn link_prefix(mux: bool, link: usize) -> String {
+    if mux {
+        format!("{}, ", link)
+    } else {
+        String::new()
+    }
+}
+
+/// The way the module hands received data to the host, shared by every socket kind
+pub fn receive_path(mux: bool, head: bool, link: usize, data: &[u8]) -> Vec<u8> {
+    let mut path = if mux {
+        format!("\r\n+RECEIVE,{},{}:\r\n", link, data.len()).into_bytes()
+    } else if head {
+        format!("\r\n+IPD,{}:", data.len()).into_bytes()
+    } else {
+        vec![]
+    };
+    path.extend_from_slice(data);
+    path
+}
+
+/// What the server and link threads share with the module, with the power cycle they belong to
+#[derive(Clone)]
+struct Shared {
+    links: Links,
+    mux: Arc<AtomicBool>,
+    head: Arc<AtomicBool>,
+    power_cycle: Arc<AtomicU64>,
+    cycle: u64,
+    tx: Sender<Vec<u8>>,
+}
+
+impl Shared {
+    /// Sends to the host unless the module was powered off or lost the radio since
+    fn send(&self, data: Vec<u8>) {
+        if self.power_cycle.load(Ordering::SeqCst) == self.cycle {
+            let _ = self.tx.send(data);
+        }
+    }
+}
+
+fn link_thread(link: usize, mut stream: TcpStream, shared: Shared) {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1460];
+        loop {
+            let read = stream.read(&mut buf);
+            let mux = shared.mux.load(Ordering::Relaxed);
+            match read {
+                Ok(0) | Err(_) => {
+                    // an empty slot means AT+CIPCLOSE already reported the close
+                    if shared.links.lock().unwrap()[link].take().is_some() {
+                        shared.send(at!(link_prefix(mux, link) + "CLOSED").into());
+                    }
+                    break;
+                }
+                Ok(n) => {
+                    let head = shared.head.load(Ordering::Relaxed);
+                    shared.send(receive_path(mux, head, link, &buf[..n]));
+                }
+            }
+        }
+    });
+}
+
+fn server_thread(listener: TcpListener, running: Arc<AtomicBool>, shared: Shared) {
+    std::thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    let multi = shared.mux.load(Ordering::Relaxed);
+                    let max = if multi { MAX_LINKS } else { 1 };
+                    let mut locked_links = shared.links.lock().unwrap();
+                    let Some(link) = locked_links.iter().take(max).position(|l| l.is_none()) else {
+                        // no free link, the module refuses the client
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
+                    };
+                    stream.set_nonblocking(false).unwrap();
+                    let Ok(reader) = stream.try_clone() else {
+                        continue;
+                    };
+                    locked_links[link] = Some(stream);
+                    drop(locked_links);
+                    let prefix = link_prefix(multi, link);
+                    shared.send(at!(format!("{}REMOTE IP: {}", prefix, addr.ip())).into());
+                    if multi {
+                        shared.send(at!(prefix + "CONNECT").into());
+                    }
+                    link_thread(link, reader, shared.clone());
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+impl Sim868 {
+    pub fn tcpip_command(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Option<Vec<String>> {
+        let answer = if line.starts_with(AT_CIPMUX) {
+            self.cipmux(line)
+        } else if line.starts_with(AT_CIPHEAD) {
+            self.ciphead(line)
+        } else if line.starts_with(AT_CIPSERVER) {
+            self.cipserver(line, tx)
+        } else if line.starts_with(AT_CIPSEND) {
+            self.cipsend(line)
+        } else if line.starts_with(AT_CIPCLOSE) {
+            self.cipclose(line)
+        } else {
+            return None;
+        };
+        Some(answer)
+    }
+
+    fn cipmux(&mut self, line: &str) -> Vec<String> {
+        if line[AT_CIPMUX.len()..].starts_with('?') {
+            return vec![at!(format!("+CIPMUX: {}", self.tcpip.mux() as u8)), at!(OK)];
+        }
+        match params(line, AT_CIPMUX).first().map(|p| p.as_str()) {
+            // the mode is locked while the server is listening
+            Some("0") | Some("1") if self.tcpip.server_port.is_some() => vec![at!(ERROR)],
+            Some("0") => {
+                self.tcpip.mux.store(false, Ordering::Relaxed);
+                vec![at!(OK)]
+            }
+            Some("1") => {
+                self.tcpip.mux.store(true, Ordering::Relaxed);
+                vec![at!(OK)]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    fn ciphead(&mut self, line: &str) -> Vec<String> {
+        if line[AT_CIPHEAD.len()..].starts_with('?') {
+            let head = self.tcpip.head.load(Ordering::Relaxed);
+            return vec![at!(format!("+CIPHEAD: {}", head as u8)), at!(OK)];
+        }
+        match params(line, AT_CIPHEAD).first().map(|p| p.as_str()) {
+            Some("0") => self.tcpip.head.store(false, Ordering::Relaxed),
+            Some("1") => self.tcpip.head.store(true, Ordering::Relaxed),
+            _ => return vec![at!(ERROR)],
+        }
+        vec![at!(OK)]
+    }
+
+    fn cipserver(&mut self, line: &str, tx: Sender<Vec<u8>>) -> Vec<String> {
+        if line[AT_CIPSERVER.len()..].starts_with('?') {
+            let status = match self.tcpip.server_port {
+                Some(port) => format!("+CIPSERVER: 1,{}", port),
+                None => "+CIPSERVER: 0".to_owned(),
+            };
+            return vec![at!(status), at!(OK)];
+        }
+        let args = params(line, AT_CIPSERVER);
+        match args.first().map(|p| p.as_str()) {
+            Some("1") => {
+                let Some(port) = args.get(1).and_then(|p| p.parse::<u16>().ok()) else {
+                    return vec![at!(ERROR)];
+                };
+                if self.tcpip.server_port.is_some() || !self.radio_on() {
+                    return vec![at!(ERROR)];
+                }
+                let bind = self.config.get("tcp.server_bind").unwrap_or("127.0.0.1");
+                let local_port = self.config.get_or("tcp.server_local_port", port);
+                let Ok(listener) = TcpListener::bind((bind, local_port)) else {
+                    return vec![at!(ERROR)];
+                };
+                listener.set_nonblocking(true).unwrap();
+                let running = Arc::new(AtomicBool::new(true));
+                let shared = Shared {
+                    links: self.tcpip.links.clone(),
+                    mux: self.tcpip.mux.clone(),
+                    head: self.tcpip.head.clone(),
+                    power_cycle: self.power_cycle.clone(),
+                    cycle: self.power_cycle.load(Ordering::SeqCst),
+                    tx,
+                };
+                server_thread(listener, running.clone(), shared);
+                self.tcpip.server_running = Some(running);
+                self.tcpip.server_port = Some(port);
+                vec![at!(OK), at!("SERVER OK")]
+            }
+            Some("0") => {
+                let Some(running) = self.tcpip.server_running.take() else {
+                    return vec![at!(ERROR)];
+                };
+                running.store(false, Ordering::Relaxed);
+                self.tcpip.server_port = None;
+                vec![at!(OK), at!("SERVER CLOSE")]
+            }
+            _ => vec![at!(ERROR)],
+        }
+    }
+
+    fn cipsend(&mut self, line: &str) -> Vec<String> {
+        let args = params(line, AT_CIPSEND);
+        let (link, length) = if self.tcpip.mux() {
+            (
+                args.first().and_then(|p| p.parse::<usize>().ok()),
+                args.get(1),
+            )
+        } else {
+            (Some(0), args.first())
+        };
+        let Some(link) = link.filter(|l| self.tcpip.is_connected(*l)) else {
+            return vec![at!(ERROR)];
+        };
+        let read = match length.map(|l| l.parse::<usize>()) {
+            None => PortControl::ReadUntilCtrlZ,
+            Some(Ok(n)) if n > 0 && n <= 1460 => PortControl::ReadExact(n),
+            Some(_) => return vec![at!(ERROR)],
+        };
+        self.expect_data(PendingInput::CipSend(link), read);
+        vec!["\r\n> ".to_owned()]
+    }
+
+    pub fn cipsend_data(&mut self, link: usize, data: &[u8]) -> Vec<String> {
+        let prefix = link_prefix(self.tcpip.mux(), link);
+        let sent = match self.tcpip.links.lock().unwrap()[link].as_mut() {
+            Some(stream) => stream.write_all(data).is_ok(),
+            None => false,
+        };
+        if sent {
+            vec![at!(prefix + "SEND OK")]
+        } else {
+            vec![at!(prefix + "SEND FAIL")]
+        }
+    }
+
+    /// Closes every link the way the remote closing it would be reported
+    pub fn hang_up(&mut self) -> Vec<String> {
+        let mut closed = vec![];
+        let mux = self.tcpip.mux();
+        for (link, slot) in self.tcpip.links.lock().unwrap().iter_mut().enumerate() {
+            if let Some(stream) = slot.take() {
+                let _ = stream.shutdown(Shutdown::Both);
+                closed.push(at!(link_prefix(mux, link) + "CLOSED"));
+            }
+        }
+        closed
+    }
+
+    fn cipclose(&mut self, line: &str) -> Vec<String> {
+        let link = if self.tcpip.mux() {
+            params(line, AT_CIPCLOSE)
+                .first()
+                .and_then(|p| p.parse::<usize>().ok())
+        } else {
+            Some(0)
+        };
+        let Some(link) = link.filter(|l| *l < MAX_LINKS) else {
+            return vec![at!(ERROR)];
+        };
+        match self.tcpip.links.lock().unwrap()[link].take() {
+            Some(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+                vec![at!(link_prefix(self.tcpip.mux(), link) + "CLOSE OK")]
+            }
+            None => vec![at!(ERROR)],
+        }
+    }
+}
@@ -2,11 +2,22 @@ use std::{
     collections::VecDeque,
     io,
     ops::Not,
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        atomic::Ordering,
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
     time::Duration,
 };
 
-use crate::sim868::{GnssConfiguration, Sim868};
+use crate::{
+    config::Config,
+    sim868::{GnssConfiguration, Injection, Sim868},
+    utils::{
+        random::Random,
+        serial::{ControlLines, PortControl},
+    },
+};
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use ratatui::{
     prelude::*,
@@ -17,8 +28,11 @@ use ratatui::{
 
 pub fn run_ui<B: Backend>(
     terminal: &mut Terminal<B>,
-    rx: Receiver<String>,
-    tx: Sender<String>,
+    rx: Receiver<Vec<u8>>,
+    tx: Sender<Vec<u8>>,
+    ctrl_tx: Sender<PortControl>,
+    lines: Arc<ControlLines>,
+    config: Config,
 ) -> io::Result<()> {
     let mut selected_button: usize = 0;
     // let button_states = &mut [State::Selected, State::Normal, State::Normal];
@@ -36,10 +50,42 @@ pub fn run_ui<B: Backend>(
     // }
     text_area.set_content_length(1000);
 
-    let mut sim_device = Sim868::new(true, GnssConfiguration::default());
+    let mut sim_device = Sim868::new(false, GnssConfiguration::from_config(&config));
+    sim_device.config = config;
+    for problem in sim_device.load_identity() {
+        text_area.add_line(problem);
+    }
+    for problem in sim_device.ntp_config_problems() {
+        text_area.add_line(problem);
+    }
+    sim_device.set_port_control(ctrl_tx);
+    sim_device.lines = lines;
     let _gnss_tx = sim_device.start_gnss(tx.clone());
+    if let Some(status) = GnssConfiguration::open_uart(&sim_device.gnss, &sim_device.config) {
+        text_area.add_line(status);
+    }
+    if sim_device.config.get_or("power.on_start", true) {
+        sim_device.set_power(true, tx.clone());
+    }
 
+    let mut last_lines = String::new();
+    let mut last_overflow = 0;
     loop {
+        sim_device.poll_lines(tx.clone());
+        // on PTY and TCP ports the lines are only virtual, the log is all there is of them
+        let lines = sim_device.lines.describe();
+        if lines != last_lines {
+            text_area.add_line(format!("Lines: {}", lines));
+            last_lines = lines;
+        }
+        let overflow = sim_device.lines.overflow.load(Ordering::Relaxed);
+        if overflow != last_overflow {
+            text_area.add_line(format!(
+                "Input buffer overflow: {} bytes lost",
+                overflow - last_overflow
+            ));
+            last_overflow = overflow;
+        }
         {
             terminal.draw(|frame| {
                 let chunks = Layout::default()
@@ -52,7 +98,7 @@ pub fn run_ui<B: Backend>(
                     .split(chunks[0]);
                 frame.render_widget(
                     Paragraph::new(
-                        "ALT + q: quit\tToggle Gnss Power: ALT=h\tToggle gsm power: ALT+g",
+                        "ALT + q: quit\tToggle Gnss Power: ALT=h\tToggle gsm power: ALT+g\tTrack speed: ALT+[ ALT+]\tTeleport: ALT+t\tJump: ALT+j\tFreeze: ALT+f\tSpeed: ALT+s\tClear: ALT+c\tDTR: ALT+d\tRTS: ALT+r",
                     )
                     .style(Style::default().bg(Color::Green)),
                     chunks[1],
@@ -86,10 +132,11 @@ pub fn run_ui<B: Backend>(
 
             let data = sim_device.process_at(&line, tx.clone()).unwrap();
             let mut answer = String::new();
-            answer += &format!("◁◁  {} -> ", &line);
+            answer += &format!("◁◁  {} -> ", String::from_utf8_lossy(&line));
             for at in data {
-                if let Ok(_) = tx.send(at.clone()) {
-                    answer += &format!("▶ {}", &at);
+                let text = String::from_utf8_lossy(&at).into_owned();
+                if let Ok(_) = tx.send(at) {
+                    answer += &format!("▶ {}", &text);
                 } else {
                     text_area.add_line("failed to send this command ->".to_string());
                 }
@@ -110,10 +157,85 @@ pub fn run_ui<B: Backend>(
                     } else if key.code == KeyCode::Down {
                         text_area.scroll_down(4);
                     } else if key.code == KeyCode::Char('g') && key.modifiers == KeyModifiers::ALT {
-                        sim_device.power = !sim_device.power;
+                        let power = !sim_device.power;
+                        sim_device.set_power(power, tx.clone());
+                        text_area.add_line(format!(
+                            "Module powered {}",
+                            if power { "on" } else { "off" }
+                        ));
                     } else if key.code == KeyCode::Char('h') && key.modifiers == KeyModifiers::ALT {
-                        let d = !sim_device.gnss.lock().unwrap().power;
-                        sim_device.gnss.lock().unwrap().power = d;
+                        let mut gnss = sim_device.gnss.lock().unwrap();
+                        let power = !gnss.power;
+                        gnss.set_power(power);
+                    } else if (key.code == KeyCode::Char(']') || key.code == KeyCode::Char('['))
+                        && key.modifiers == KeyModifiers::ALT
+                    {
+                        if let Some(track) = sim_device.gnss.lock().unwrap().track.as_mut() {
+                            let factor = if key.code == KeyCode::Char(']') {
+                                2.0
+                            } else {
+                                0.5
+                            };
+                            track.set_rate(track.rate * factor);
+                        }
+                    } else if key.code == KeyCode::Char('r') && key.modifiers == KeyModifiers::ALT {
+                        let rts = !sim_device.lines.rts.load(Ordering::Relaxed);
+                        sim_device.lines.rts.store(rts, Ordering::Relaxed);
+                    } else if key.code == KeyCode::Char('d') && key.modifiers == KeyModifiers::ALT {
+                        let dtr = !sim_device.lines.dtr.load(Ordering::Relaxed);
+                        sim_device.lines.dtr.store(dtr, Ordering::Relaxed);
+                        text_area
+                            .add_line(format!("DTR {}", if dtr { "asserted" } else { "released" }));
+                    } else if key.modifiers == KeyModifiers::ALT
+                        && matches!(key.code, KeyCode::Char('t' | 'j' | 'f' | 's' | 'c'))
+                    {
+                        let config = &sim_device.config;
+                        let mut gnss = sim_device.gnss.lock().unwrap();
+                        // an expired timed speed must not turn ALT+s into a release
+                        gnss.deviation.expire();
+                        let injection = match key.code {
+                            KeyCode::Char('t') => {
+                                let teleport = config
+                                    .get("gnss.teleport")
+                                    .and_then(|p| p.split_once(','))
+                                    .and_then(|(lat, lon)| {
+                                        Some(Injection::Teleport {
+                                            latitude: lat.trim().parse().ok()?,
+                                            longitude: lon.trim().parse().ok()?,
+                                        })
+                                    });
+                                if teleport.is_none() {
+                                    text_area.add_line(
+                                        "ALT+t: gnss.teleport is not set to <latitude>,<longitude>"
+                                            .to_owned(),
+                                    );
+                                }
+                                teleport
+                            }
+                            KeyCode::Char('j') => Some(Injection::Jump {
+                                meters: config.get_or("gnss.jump_m", 2000.0),
+                                bearing: Random::new().below(360) as f64,
+                                seconds: config.get_or("gnss.jump_s", 5.0),
+                            }),
+                            KeyCode::Char('f') if gnss.deviation.frozen.is_some() => {
+                                Some(Injection::Unfreeze)
+                            }
+                            KeyCode::Char('f') => Some(Injection::Freeze),
+                            KeyCode::Char('s') if gnss.deviation.speed.is_some() => {
+                                gnss.deviation.speed = None;
+                                text_area.add_line("GNSS speed released".to_owned());
+                                None
+                            }
+                            KeyCode::Char('s') => Some(Injection::Speed {
+                                kmh: config.get_or("gnss.impossible_speed_kmh", 1200.0),
+                                seconds: 0.0,
+                            }),
+                            _ => Some(Injection::Clear),
+                        };
+                        if let Some(injection) = injection {
+                            text_area.add_line(format!("GNSS injection: {}", injection));
+                            gnss.inject(injection);
+                        }
                     } else if key.code == KeyCode::Char('1')
                         || key.code == KeyCode::Char('2')
                         || key.code == KeyCode::Char('3')
@@ -1,36 +1,351 @@
 pub mod serial {
     use std::{
-        sync::mpsc::{channel, Receiver, Sender},
-        time::Duration,
+        collections::VecDeque,
+        io::Write,
+        sync::{
+            atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+            mpsc::{channel, Receiver, Sender},
+            Arc,
+        },
+        time::{Duration, Instant},
     };
 
-    pub fn read_line_thread(port_name: String, port_rx: Receiver<String>) -> Receiver<String> {
-        let (tx, rx) = channel::<String>();
+    use crate::config::Config;
+
+    /// Requests from the emulator that change how the port thread reads the host input
+    pub enum PortControl {
+        /// hand the next `n` bytes over as one chunk instead of splitting them on line ends
+        ReadExact(usize),
+        /// hand everything up to the next Ctrl-Z over as one chunk
+        ReadUntilCtrlZ,
+        /// drop the chunk being read and go back to lines
+        ReadLines,
+        /// change the port speed once what is queued has gone out, 0 hunts for the host's
+        /// speed until a line with `AT` comes in
+        Baud(u32),
+    }
+
+    /// No flow control, `AT+IFC` value
+    pub const FLOW_NONE: u8 = 0;
+    /// XON/XOFF in the data
+    pub const FLOW_SOFTWARE: u8 = 1;
+    /// RTS/CTS
+    pub const FLOW_HARDWARE: u8 = 2;
+    const XON: u8 = 0x11;
+    const XOFF: u8 = 0x13;
+
+    /// Modem control lines of the module's UART, shared between the port thread and the emulator.
+    /// The outputs are driven on the PC port pins `Wiring` gives them, the others stay virtual.
+    pub struct ControlLines {
+        /// host DTR asserted, it comes in on our DSR or DCD pin through a null modem cable
+        pub dtr: AtomicBool,
+        /// host RTS asserted, it comes in on our CTS pin
+        pub rts: AtomicBool,
+        /// the module is on and awake, `clear_to_send` also takes the input buffer into account
+        pub cts: AtomicBool,
+        pub dcd: AtomicBool,
+        pub ri: AtomicBool,
+        /// how the host stops the module's output, the first `AT+IFC` value
+        pub output_flow: AtomicU8,
+        /// how the module stops the host's input, the second `AT+IFC` value
+        pub input_flow: AtomicU8,
+        /// the input buffer is filling up, CTS drops under hardware flow control
+        pub rx_busy: AtomicBool,
+        /// bytes lost to a full input buffer
+        pub overflow: AtomicUsize,
+    }
+
+    impl ControlLines {
+        pub fn new() -> ControlLines {
+            ControlLines {
+                dtr: AtomicBool::new(true),
+                rts: AtomicBool::new(true),
+                cts: AtomicBool::new(false),
+                dcd: AtomicBool::new(false),
+                ri: AtomicBool::new(false),
+                output_flow: AtomicU8::new(FLOW_NONE),
+                input_flow: AtomicU8::new(FLOW_NONE),
+                rx_busy: AtomicBool::new(false),
+                overflow: AtomicUsize::new(0),
+            }
+        }
+
+        /// What the module's CTS output shows: under hardware flow control it drops while the
+        /// input buffer is filling up
+        pub fn clear_to_send(&self) -> bool {
+            let busy = self.input_flow.load(Ordering::Relaxed) == FLOW_HARDWARE
+                && self.rx_busy.load(Ordering::Relaxed);
+            self.cts.load(Ordering::Relaxed) && !busy
+        }
+
+        /// One line per signal as the UI logs them
+        pub fn describe(&self) -> String {
+            let state = |on: bool| if on { "on" } else { "off" };
+            let line = |line: &AtomicBool| state(line.load(Ordering::Relaxed));
+            format!(
+                "DTR {}  RTS {}  CTS {}  DCD {}  RI {}",
+                line(&self.dtr),
+                line(&self.rts),
+                state(self.clear_to_send()),
+                line(&self.dcd),
+                line(&self.ri)
+            )
+        }
+    }
+
+    /// The module's input buffer, `size` 0 never fills
+    pub struct InputBuffer {
+        pub size: usize,
+        /// bytes a second the module takes out of it
+        pub drain_bps: f64,
+    }
+
+    impl InputBuffer {
+        pub fn from_config(config: &Config) -> InputBuffer {
+            InputBuffer {
+                size: config.get_or("serial.rx_buffer", 0),
+                drain_bps: config.get_or("serial.rx_drain_bps", 2000.0),
+            }
+        }
+    }
+
+    /// Pins of the PC port the module's control lines are wired to, `dsr` or `dcd` for the DTR
+    /// input, `cts` for the RTS input and `rts` or `dtr` for the outputs; anything else keeps the
+    /// line virtual
+    pub struct Wiring {
+        pub dtr: String,
+        pub rts: String,
+        pub cts: String,
+        pub dcd: String,
+        pub ri: String,
+    }
+
+    impl Wiring {
+        /// A null modem cable: the host DTR on our DSR, its RTS on our CTS, our RTS on its CTS and
+        /// our DTR on its DCD
+        pub fn from_config(config: &Config) -> Wiring {
+            let pin = |key: &str, default: &str| config.get(key).unwrap_or(default).to_owned();
+            Wiring {
+                dtr: pin("serial.dtr_line", "dsr"),
+                rts: pin("serial.rts_line", "cts"),
+                cts: pin("serial.cts_line", "rts"),
+                dcd: pin("serial.dcd_line", "dtr"),
+                ri: pin("serial.ri_line", "none"),
+            }
+        }
+    }
+
+    const CTRL_Z: u8 = 0x1A;
+    /// how often the input control lines are read
+    const LINE_POLL: Duration = Duration::from_millis(20);
+    /// quiet time on the output before a baud rate change takes effect
+    const BAUD_SETTLE: Duration = Duration::from_millis(20);
+    /// speeds auto-bauding tries in turn until the host input reads clean
+    pub const AUTOBAUD_RATES: [u32; 8] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+
+    /// Serves the host port: lines go to the emulator, the control lines follow `lines` as wired.
+    /// `baud` 0 starts auto-bauding.
+    pub fn read_line_thread(
+        port_name: String,
+        baud: u32,
+        port_rx: Receiver<Vec<u8>>,
+        ctrl_rx: Receiver<PortControl>,
+        lines: Arc<ControlLines>,
+        wiring: Wiring,
+        buffer: InputBuffer,
+    ) -> Receiver<Vec<u8>> {
+        let (tx, rx) = channel::<Vec<u8>>();
 
         // perform conncetion to the port
 
         std::thread::spawn(move || {
-            let mut port = serialport::new(&port_name, 115_200)
+            let mut hunting = baud == 0;
+            // no FlowControl::Hardware: the driver would take over RTS and CTS, while `Wiring`
+            // may put the module's lines on other pins and AT+IFC switches the flow control at
+            // run time, so both directions are handled here
+            let mut port = serialport::new(&port_name, if hunting { 115_200 } else { baud })
                 .timeout(Duration::from_millis(10))
                 .open()
                 .expect("Failed to open port");
-            let mut serial_buf: Vec<u8> = vec![0; 1];
-            let mut big_buffer: Vec<u8> = vec![0; 1000];
+            let mut big_buffer: Vec<u8> = Vec::with_capacity(1000);
+            let mut raw_read: Option<PortControl> = None;
+            let mut raw_buffer: Vec<u8> = vec![];
+            let mut lines_polled = Instant::now();
+            let mut last_dtr = None;
+            let mut last_rts = None;
+            let mut driven: [Option<bool>; 3] = [None; 3];
+            let mut baud_change: Option<(u32, Instant)> = None;
+            // bytes in the module's input buffer, it empties at `buffer.drain_bps`
+            let mut fill = 0.0;
+            let mut drained = Instant::now();
+            // read from the port but not yet handed over
+            let mut backlog: VecDeque<u8> = VecDeque::new();
+            let mut xoff_received = false;
             loop {
-                if let Ok(to_send) = port_rx.recv_timeout(Duration::from_millis(2)) {
-                    let bytes_written = port.write(to_send.as_bytes()).unwrap();
+                if lines_polled.elapsed() >= LINE_POLL {
+                    lines_polled = Instant::now();
+                    let dtr = match wiring.dtr.as_str() {
+                        "dsr" => port.read_data_set_ready().ok(),
+                        "dcd" => port.read_carrier_detect().ok(),
+                        _ => None,
+                    };
+                    // only changes are taken, so the UI can still toggle the line in between
+                    if dtr.is_some() && dtr != last_dtr {
+                        lines.dtr.store(dtr.unwrap_or(true), Ordering::Relaxed);
+                        last_dtr = dtr;
+                    }
+                    let rts = match wiring.rts.as_str() {
+                        "cts" => port.read_clear_to_send().ok(),
+                        _ => None,
+                    };
+                    if rts.is_some() && rts != last_rts {
+                        lines.rts.store(rts.unwrap_or(true), Ordering::Relaxed);
+                        last_rts = rts;
+                    }
+                }
+                let paused = match lines.output_flow.load(Ordering::Relaxed) {
+                    FLOW_SOFTWARE => xoff_received,
+                    FLOW_HARDWARE => !lines.rts.load(Ordering::Relaxed),
+                    _ => false,
+                };
+                if paused {
+                    // what the module has to say waits in the channel until the host is ready
+                } else if let Ok(to_send) = port_rx.recv_timeout(Duration::from_millis(2)) {
+                    port.write_all(&to_send).unwrap();
+                    // the answer to AT+IPR still goes out at the old speed
+                    if let Some((_, due)) = baud_change.as_mut() {
+                        *due = Instant::now() + BAUD_SETTLE;
+                    }
+                }
+                if let Some((rate, _)) = baud_change.filter(|(_, due)| Instant::now() >= *due) {
+                    baud_change = None;
+                    let _ = port.flush();
+                    hunting = rate == 0;
+                    if !hunting {
+                        let _ = port.set_baud_rate(rate);
+                    }
+                }
+                // checked after writing so a data prompt never reaches the host before the mode switch
+                if let Ok(ctrl) = ctrl_rx.try_recv() {
+                    match ctrl {
+                        PortControl::Baud(rate) => {
+                            baud_change = Some((rate, Instant::now() + BAUD_SETTLE));
+                        }
+                        PortControl::ReadLines => raw_read = None,
+                        ctrl => raw_read = Some(ctrl),
+                    }
+                    raw_buffer.clear();
+                }
+
+                if buffer.size > 0 {
+                    fill = (fill - buffer.drain_bps * drained.elapsed().as_secs_f64()).max(0.0);
+                    drained = Instant::now();
+                    let busy = lines.rx_busy.load(Ordering::Relaxed);
+                    // raised at three quarters full, released at a quarter
+                    let now_busy = if busy {
+                        fill > buffer.size as f64 / 4.0
+                    } else {
+                        fill > buffer.size as f64 * 3.0 / 4.0
+                    };
+                    if now_busy != busy {
+                        lines.rx_busy.store(now_busy, Ordering::Relaxed);
+                        if lines.input_flow.load(Ordering::Relaxed) == FLOW_SOFTWARE {
+                            let _ = port.write_all(&[if now_busy { XOFF } else { XON }]);
+                        }
+                    }
+                }
+                // driven every turn, so CTS drops as soon as the buffer fills
+                let outputs = [
+                    (&wiring.cts, lines.clear_to_send()),
+                    (&wiring.dcd, lines.dcd.load(Ordering::Relaxed)),
+                    (&wiring.ri, lines.ri.load(Ordering::Relaxed)),
+                ];
+                for (i, (pin, on)) in outputs.into_iter().enumerate() {
+                    if driven[i] == Some(on) {
+                        continue;
+                    }
+                    driven[i] = Some(on);
+                    let _ = match pin.as_str() {
+                        "rts" => port.write_request_to_send(on),
+                        "dtr" => port.write_data_terminal_ready(on),
+                        _ => Ok(()),
+                    };
+                }
+                let mut chunk = [0u8; 256];
+                let received = port.read(&mut chunk).unwrap_or(0);
+                for &byte in &chunk[..received] {
+                    if lines.output_flow.load(Ordering::Relaxed) == FLOW_SOFTWARE
+                        && (byte == XOFF || byte == XON)
+                    {
+                        xoff_received = byte == XOFF;
+                        continue;
+                    }
+                    // a host that ignores the flow control overruns the buffer
+                    if buffer.size > 0 && fill >= buffer.size as f64 {
+                        lines.overflow.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    fill += 1.0;
+                    backlog.push_back(byte);
                 }
-                if port.read_exact(&mut serial_buf).is_ok() {
-                    big_buffer.push(serial_buf[0]);
-                    if serial_buf[0] == '\n' as u8 {
+
+                // one chunk at most per turn, the emulator may switch the read mode after it
+                while let Some(byte) = backlog.pop_front() {
+                    if let Some(mode) = &raw_read {
+                        let done = match mode {
+                            PortControl::ReadExact(n) => {
+                                raw_buffer.push(byte);
+                                raw_buffer.len() >= *n
+                            }
+                            PortControl::ReadUntilCtrlZ => {
+                                if byte != CTRL_Z {
+                                    raw_buffer.push(byte);
+                                }
+                                byte == CTRL_Z
+                            }
+                            PortControl::ReadLines | PortControl::Baud(_) => true,
+                        };
+                        if done {
+                            tx.send(std::mem::take(&mut raw_buffer)).unwrap();
+                            raw_read = None;
+                            break;
+                        }
+                        continue;
+                    }
+                    // at the wrong speed the host input reads as garbage, so try the next one
+                    if hunting && !(byte.is_ascii_graphic() || byte.is_ascii_whitespace()) {
+                        let current = port.baud_rate().unwrap_or(115_200);
+                        let next = AUTOBAUD_RATES
+                            .iter()
+                            .position(|r| *r == current)
+                            .map(|i| AUTOBAUD_RATES[(i + 1) % AUTOBAUD_RATES.len()])
+                            .unwrap_or(AUTOBAUD_RATES[0]);
+                        let _ = port.set_baud_rate(next);
+                        // the rest of the garbled burst says nothing more about the speed
+                        let _ = port.clear(serialport::ClearBuffer::Input);
+                        backlog.clear();
+                        big_buffer.clear();
+                        break;
+                    }
+                    big_buffer.push(byte);
+                    if hunting && byte == b'\n' {
+                        // the module locks on the first line that reads `AT`
+                        let text = String::from_utf8_lossy(&big_buffer).to_uppercase();
+                        if !text.contains("AT") {
+                            big_buffer.clear();
+                            continue;
+                        }
+                        hunting = false;
+                    }
+                    if byte == b'\n' {
                         match std::str::from_utf8(&big_buffer) {
                             Ok(buffer_str) => {
                                 if let Some((line, _)) = buffer_str.split_once("\r\n") {
-                                    tx.send(line.to_string()).unwrap();
+                                    tx.send(line.as_bytes().to_vec()).unwrap();
                                     big_buffer.clear();
                                     // return Some(line.into());
                                 } else if let Some((line, _)) = buffer_str.split_once('\n') {
-                                    tx.send(line.to_string()).unwrap();
+                                    tx.send(line.as_bytes().to_vec()).unwrap();
                                     big_buffer.clear();
 
                                     // return Some(line.into());
@@ -52,6 +367,7 @@ pub mod serial {
                                 big_buffer.clear();
                             }
                         }
+                        break;
                     }
                 }
 
@@ -61,3 +377,214 @@ pub mod serial {
         return rx;
     }
 }
+
+pub mod random {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Small xorshift generator, good enough for jitter and simulated losses
+    pub struct Random {
+        state: u64,
+    }
+
+    impl Random {
+        pub fn new() -> Random {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0x2545_f491_4f6c_dd1d);
+            Random { state: seed | 1 }
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            self.state
+        }
+
+        /// uniform value in `0..n`, zero when `n` is zero
+        pub fn below(&mut self, n: u64) -> u64 {
+            if n == 0 {
+                0
+            } else {
+                self.next_u64() % n
+            }
+        }
+
+        /// true with the given probability in percent
+        pub fn chance(&mut self, percent: u64) -> bool {
+            self.below(100) < percent
+        }
+    }
+}
+
+pub mod timer {
+    use std::time::{Duration, Instant};
+
+    /// Fires every `period`, a tick that comes late is skipped instead of bunching up the next ones
+    pub struct Ticker {
+        period: Duration,
+        next: Instant,
+    }
+
+    impl Ticker {
+        /// The first tick comes a full period from now
+        pub fn new(period: Duration) -> Ticker {
+            Ticker {
+                period,
+                next: Instant::now() + period,
+            }
+        }
+
+        /// Time left until the next tick, zero once it is due
+        pub fn remaining(&self) -> Duration {
+            self.next.saturating_duration_since(Instant::now())
+        }
+
+        /// Moves on to the next tick still ahead
+        pub fn tick(&mut self) {
+            let now = Instant::now();
+            self.next += self.period;
+            if self.next <= now {
+                self.next = now + self.period;
+            }
+        }
+
+        /// Starts over with a new period
+        pub fn restart(&mut self, period: Duration) {
+            *self = Ticker::new(period);
+        }
+    }
+}
+
+pub mod time {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Calendar form of a UTC instant, what the module prints in its time fields
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct DateTime {
+        pub year: i64,
+        pub month: u32,
+        pub day: u32,
+        pub hour: u32,
+        pub minute: u32,
+        pub second: u32,
+        pub millis: u32,
+    }
+
+    /// Seconds since the unix epoch, with millisecond fraction
+    pub fn now() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    impl DateTime {
+        pub fn from_unix(timestamp: f64) -> DateTime {
+            let secs = timestamp.floor() as i64;
+            let millis = ((timestamp - secs as f64) * 1000.0) as u32;
+            let days = secs.div_euclid(86400);
+            let rem = secs.rem_euclid(86400) as u32;
+            // days to civil date, after Howard Hinnant's algorithm
+            let z = days + 719468;
+            let era = z.div_euclid(146097);
+            let doe = z.rem_euclid(146097);
+            let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+            let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+            let mp = (5 * doy + 2) / 153;
+            let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+            let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+            let year = yoe + era * 400 + i64::from(month <= 2);
+            DateTime {
+                year,
+                month,
+                day,
+                hour: rem / 3600,
+                minute: rem % 3600 / 60,
+                second: rem % 60,
+                millis,
+            }
+        }
+
+        pub fn to_unix(self) -> f64 {
+            let year = self.year - i64::from(self.month <= 2);
+            let era = year.div_euclid(400);
+            let yoe = year.rem_euclid(400);
+            let mp = (i64::from(self.month) + 9) % 12;
+            let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+            let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+            let days = era * 146097 + doe - 719468;
+            (days * 86400
+                + i64::from(self.hour) * 3600
+                + i64::from(self.minute) * 60
+                + i64::from(self.second)) as f64
+                + f64::from(self.millis) / 1000.0
+        }
+
+        /// Reads `YYYY-MM-DDThh:mm:ss[.sss][Z|±hh:mm]` as found in GPX and KML files
+        pub fn parse_iso8601(text: &str) -> Option<DateTime> {
+            let text = text.trim();
+            let (date, rest) = text.split_once(['T', ' '])?;
+            let mut date = date.split('-');
+            let year = date.next()?.parse().ok()?;
+            let month = date.next()?.parse().ok()?;
+            let day = date.next()?.parse().ok()?;
+            let zone_at = rest.find(['Z', '+', '-']).unwrap_or(rest.len());
+            let (clock, zone) = rest.split_at(zone_at);
+            let mut clock = clock.split(':');
+            let hour = clock.next()?.parse().ok()?;
+            let minute = clock.next()?.parse().ok()?;
+            let seconds: f64 = clock.next().unwrap_or("0").parse().ok()?;
+            let mut time = DateTime {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second: seconds as u32,
+                millis: (seconds.fract() * 1000.0).round() as u32,
+            };
+            if let Some(offset) = zone.get(1..).filter(|_| !zone.starts_with('Z')) {
+                let (h, m) = offset.split_once(':').unwrap_or((offset, "0"));
+                let minutes = h.parse::<i64>().ok()? * 60 + m.parse::<i64>().ok()?;
+                let sign = if zone.starts_with('-') { 1.0 } else { -1.0 };
+                time = DateTime::from_unix(time.to_unix() + sign * minutes as f64 * 60.0);
+            }
+            Some(time)
+        }
+    }
+}
+
+pub mod geo {
+    const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+    /// Great circle distance between two points in meters
+    pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+        let dphi = (lat2 - lat1).to_radians();
+        let dlambda = (lon2 - lon1).to_radians();
+        let a =
+            (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+
+    /// Initial course from the first point to the second, degrees clockwise from north
+    pub fn bearing_deg(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+        let dlambda = (lon2 - lon1).to_radians();
+        let y = dlambda.sin() * phi2.cos();
+        let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * dlambda.cos();
+        (y.atan2(x).to_degrees() + 360.0) % 360.0
+    }
+
+    /// The point `distance` meters away from the given one along `bearing` degrees
+    pub fn offset(lat: f64, lon: f64, distance: f64, bearing: f64) -> (f64, f64) {
+        let (phi1, lambda1) = (lat.to_radians(), lon.to_radians());
+        let (delta, theta) = (distance / EARTH_RADIUS_M, bearing.to_radians());
+        let phi2 = (phi1.sin() * delta.cos() + phi1.cos() * delta.sin() * theta.cos()).asin();
+        let lambda2 = lambda1
+            + (theta.sin() * delta.sin() * phi1.cos()).atan2(delta.cos() - phi1.sin() * phi2.sin());
+        (phi2.to_degrees(), lambda2.to_degrees())
+    }
+}